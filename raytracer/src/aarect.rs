@@ -1,6 +1,7 @@
 use crate::aabb::Aabb;
 
-use crate::{HitRecord, Hittable, Material, Point3, Ray, Vec3};
+use crate::sampler::next_2d;
+use crate::{Color1, HitRecord, Hittable, Material, Point3, Ray, Vec3, INFINITY};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -59,9 +60,33 @@ impl Hittable for XyRect {
         let outward_normal = Vec3::new(0.0, 0.0, 1.0);
         rec.set_face_normal(r, &outward_normal);
         rec.mat_ptr = (*self).clone().mp;
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, self.y1 - self.y0, 0.0);
         rec.p = r.at(t);
         true
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (s, t) = next_2d();
+        let random_point = Point3::new(
+            self.x0 + (self.x1 - self.x0) * s,
+            self.y0 + (self.y1 - self.y0) * t,
+            self.k,
+        );
+        random_point - *o
+    }
 }
 
 #[derive(Clone)]
@@ -120,9 +145,33 @@ impl Hittable for XzRect {
         let outward_normal = Vec3::new(0.0, 1.0, 0.0);
         rec.set_face_normal(r, &outward_normal);
         rec.mat_ptr = (*self).clone().mp;
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         rec.p = r.at(t);
         true
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (s, t) = next_2d();
+        let random_point = Point3::new(
+            self.x0 + (self.x1 - self.x0) * s,
+            self.k,
+            self.z0 + (self.z1 - self.z0) * t,
+        );
+        random_point - *o
+    }
 }
 
 #[derive(Clone)]
@@ -181,7 +230,31 @@ impl Hittable for YzRect {
         let outward_normal = Vec3::new(1.0, 0.0, 0.0);
         rec.set_face_normal(r, &outward_normal);
         rec.mat_ptr = (*self).clone().mp;
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
+        rec.dpdu = Vec3::new(0.0, self.y1 - self.y0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         rec.p = r.at(t);
         true
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (s, t) = next_2d();
+        let random_point = Point3::new(
+            self.k,
+            self.y0 + (self.y1 - self.y0) * s,
+            self.z0 + (self.z1 - self.z0) * t,
+        );
+        random_point - *o
+    }
 }
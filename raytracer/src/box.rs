@@ -1,6 +1,6 @@
 use crate::aabb::Aabb;
 use crate::aarect::{XyRect, XzRect, YzRect};
-use crate::{HitRecord, Hittable, HittableList, Material, Point3, Ray};
+use crate::{HitRecord, Hittable, HittableList, Material, Point3, Ray, Vec3};
 use std::sync::Arc;
 
 pub struct Box1 {
@@ -80,4 +80,12 @@ impl Hittable for Box1 {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         self.sides.hit(r, t_min, t_max, &mut *rec)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.sides.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        self.sides.random(o)
+    }
 }
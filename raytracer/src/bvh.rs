@@ -1,139 +1,478 @@
-use crate::{aabb, hittable, hittable_list, rtweekend, vec3, HitRecord, Point3, Ray};
+use crate::linear_bvh::LinearBvh;
+use crate::{aabb, hittable, hittable_list, rtweekend, HitRecord, Point3, Ray};
 use aabb::Aabb;
+use clap::ValueEnum;
 pub use hittable::Hittable;
 pub use hittable_list::HittableList;
-pub use rtweekend::random_i32;
+use rtweekend::Pcg32;
+use std::fmt;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub use vec3::Vec3;
+type HittablePtr = Option<Arc<dyn Hittable + Send + Sync>>;
 
-#[derive(Clone)]
-pub struct BvhNode {
-    left: Option<Arc<dyn Hittable + Send + Sync>>,
-    right: Option<Arc<dyn Hittable + Send + Sync>>,
-    box1: Aabb,
+/// Number of centroid bins the SAH builder evaluates per axis.
+const SAH_BINS: usize = 16;
+/// Cost of visiting a node relative to intersecting one primitive, for the SAH.
+const TRAVERSAL_COST: f64 = 1.0;
+/// Smallest number of objects worth handing to another thread during a build.
+pub const PARALLEL_MIN_ITEMS: usize = 4096;
+
+/// How BVH builders partition their objects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitMethod {
+    /// Sort along a random axis and split at the median
+    Median,
+    /// Binned surface area heuristic over all three axes
+    Sah,
 }
 
-impl BvhNode {
-    pub fn box_compare(
-        a: &Option<Arc<dyn Hittable + Send + Sync>>,
-        b: &Option<Arc<dyn Hittable + Send + Sync>>,
-        axis: i32,
-    ) -> bool {
-        let mut box_a: Aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
-        let mut box_b: Aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
-
-        if !a.clone().unwrap().bounding_box(0.0, 0.0, &mut box_a)
-            || !b.clone().unwrap().bounding_box(0.0, 0.0, &mut box_b)
+impl fmt::Display for SplitMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitMethod::Median => write!(f, "median"),
+            SplitMethod::Sah => write!(f, "sah"),
+        }
+    }
+}
+
+/// How a built BVH is laid out in memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BvhLayout {
+    /// A tree of `BvhNode`s, traversed recursively
+    Tree,
+    /// A `LinearBvh` with nodes in one array, traversed with a stack
+    Flat,
+}
+
+/// How scene BVHs are built.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BvhOptions {
+    pub method: SplitMethod,
+    pub layout: BvhLayout,
+    /// Number of threads the builds split their work over.
+    pub threads: usize,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self {
+            method: SplitMethod::Sah,
+            layout: BvhLayout::Flat,
+            threads: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        }
+    }
+}
+
+impl BvhOptions {
+    /// Builds a BVH over `list` on `threads` threads; an empty list stays as it is, having
+    /// no bounding box to build one from.
+    pub fn build(
+        &self,
+        list: &HittableList,
+        time0: f64,
+        time1: f64,
+    ) -> (Arc<dyn Hittable + Send + Sync>, BvhStats) {
+        if list.objects.is_empty() {
+            let stats = StatsBuilder::new(self.method, 0).finish();
+            return (Arc::new(list.clone()), stats);
+        }
+        match self.layout {
+            BvhLayout::Tree => {
+                let (node, stats) =
+                    BvhNode::build(&list.objects, self.method, time0, time1, self.threads);
+                (Arc::new(node), stats)
+            }
+            BvhLayout::Flat => {
+                let (bvh, stats) =
+                    LinearBvh::build(&list.objects, self.method, time0, time1, self.threads);
+                (Arc::new(bvh), stats)
+            }
+        }
+    }
+}
+
+/// Builds the BVHs of one scene with the same options, keeping the statistics of each.
+pub struct BvhBuilder {
+    options: BvhOptions,
+    stats: Vec<BvhStats>,
+}
+
+impl BvhBuilder {
+    pub fn new(options: BvhOptions) -> Self {
+        Self {
+            options,
+            stats: Vec::new(),
+        }
+    }
+
+    pub fn build(
+        &mut self,
+        list: &HittableList,
+        time0: f64,
+        time1: f64,
+    ) -> Arc<dyn Hittable + Send + Sync> {
+        let (bvh, stats) = self.options.build(list, time0, time1);
+        self.stats.push(stats);
+        bvh
+    }
+
+    /// The statistics of every BVH built, in build order.
+    pub fn finish(self) -> Vec<BvhStats> {
+        self.stats
+    }
+}
+
+/// Shape and build time of one BVH.
+#[derive(Clone, Debug)]
+pub struct BvhStats {
+    pub method: SplitMethod,
+    pub primitives: usize,
+    pub nodes: usize,
+    pub max_depth: usize,
+    /// Expected cost of a ray through the tree under the surface area heuristic,
+    /// in primitive intersections; lower is better.
+    pub sah_cost: f64,
+    pub build_time: Duration,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes, depth {}, SAH cost {:.2}, built with {} in {:?}",
+            self.primitives,
+            self.nodes,
+            self.max_depth,
+            self.sah_cost,
+            self.method,
+            self.build_time
+        )
+    }
+}
+
+/// A primitive with its bounds, computed once for the whole build. Scene BVHs hold objects;
+/// meshes hold triangle indices.
+pub struct BuildItem<T = HittablePtr> {
+    pub object: T,
+    pub bbox: Aabb,
+    centroid: Point3,
+}
+
+impl<T> BuildItem<T> {
+    pub fn with_bounds(object: T, bbox: Aabb) -> Self {
+        let centroid = (bbox.min() + bbox.max()) * 0.5;
+        BuildItem {
+            object,
+            bbox,
+            centroid,
+        }
+    }
+}
+
+impl BuildItem {
+    fn new(object: &HittablePtr, time0: f64, time1: f64) -> Self {
+        let mut bbox = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
+        if !object
+            .clone()
+            .unwrap()
+            .bounding_box(time0, time1, &mut bbox)
         {
             eprintln!("No bounding box in bvh_node constructor.\n");
         }
-
-        box_a.min()[axis as usize] < box_b.min()[axis as usize]
+        BuildItem::with_bounds(object.clone(), bbox)
     }
 
-    pub fn box_x_compare(
-        a: &Option<Arc<dyn Hittable + Send + Sync>>,
-        b: &Option<Arc<dyn Hittable + Send + Sync>>,
-    ) -> bool {
-        BvhNode::box_compare(a, b, 0)
+    /// Computes the bounds of `objects`, split across up to `threads` threads.
+    pub fn from_objects(
+        objects: &[HittablePtr],
+        time0: f64,
+        time1: f64,
+        threads: usize,
+    ) -> Vec<BuildItem> {
+        let chunk_size = objects
+            .len()
+            .div_ceil(threads.max(1))
+            .max(PARALLEL_MIN_ITEMS);
+        if objects.len() <= chunk_size {
+            return objects
+                .iter()
+                .map(|object| BuildItem::new(object, time0, time1))
+                .collect();
+        }
+        thread::scope(|s| {
+            let handles: Vec<_> = objects
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|object| BuildItem::new(object, time0, time1))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
     }
+}
+
+/// How many levels of the tree fork their second subtree onto a new thread, so that about
+/// `threads` subtrees are built at once.
+pub fn fork_levels(threads: usize) -> usize {
+    (usize::BITS - threads.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Totals up `BvhStats` as a builder creates nodes.
+pub struct StatsBuilder {
+    method: SplitMethod,
+    primitives: usize,
+    start: Instant,
+    root_area: f64,
+    nodes: usize,
+    max_depth: usize,
+    sah_cost: f64,
+}
 
-    pub fn box_y_compare(
-        a: &Option<Arc<dyn Hittable + Send + Sync>>,
-        b: &Option<Arc<dyn Hittable + Send + Sync>>,
-    ) -> bool {
-        BvhNode::box_compare(a, b, 1)
+impl StatsBuilder {
+    /// Starts the build clock; call before computing the bounds of `items`.
+    pub fn new(method: SplitMethod, primitives: usize) -> Self {
+        Self {
+            method,
+            primitives,
+            start: Instant::now(),
+            root_area: 0.0,
+            nodes: 0,
+            max_depth: 0,
+            sah_cost: 0.0,
+        }
     }
 
-    pub fn box_z_compare(
-        a: &Option<Arc<dyn Hittable + Send + Sync>>,
-        b: &Option<Arc<dyn Hittable + Send + Sync>>,
-    ) -> bool {
-        BvhNode::box_compare(a, b, 2)
+    /// Records a node at `depth` (the root is 1) that intersects `primitives` objects itself.
+    pub fn node(&mut self, bbox: &Aabb, depth: usize, primitives: usize) {
+        if depth == 1 {
+            self.root_area = bbox.surface_area();
+        }
+        let relative_area = if self.root_area > 0.0 {
+            bbox.surface_area() / self.root_area
+        } else {
+            1.0
+        };
+        self.nodes += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.sah_cost += relative_area * (TRAVERSAL_COST + primitives as f64);
     }
 
-    pub fn new0() -> Self {
+    /// An empty builder for a subtree built on another thread, to `merge` back afterwards.
+    pub fn fork(&self) -> Self {
         Self {
-            left: None,
-            right: None,
-            box1: Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)),
+            primitives: 0,
+            nodes: 0,
+            max_depth: 0,
+            sah_cost: 0.0,
+            ..*self
+        }
+    }
+
+    pub fn merge(&mut self, other: StatsBuilder) {
+        self.nodes += other.nodes;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.sah_cost += other.sah_cost;
+    }
+
+    pub fn finish(self) -> BvhStats {
+        BvhStats {
+            method: self.method,
+            primitives: self.primitives,
+            nodes: self.nodes,
+            max_depth: self.max_depth,
+            sah_cost: self.sah_cost,
+            build_time: self.start.elapsed(),
         }
     }
+}
 
-    pub fn new(
-        src_objects: &mut [Option<Arc<dyn Hittable + Send + Sync>>],
-        start: usize,
-        end: usize,
+/// A node holds either two subtrees or up to two primitives; a single primitive sits in `left`.
+#[derive(Clone)]
+pub struct BvhNode {
+    left: HittablePtr,
+    right: HittablePtr,
+    box1: Aabb,
+}
+
+impl BvhNode {
+    /// Builds a tree over `src_objects` with the given split method, on up to `threads`
+    /// threads. The tree does not depend on the number of threads.
+    pub fn build(
+        src_objects: &[HittablePtr],
+        method: SplitMethod,
         time0: f64,
         time1: f64,
+        threads: usize,
+    ) -> (Self, BvhStats) {
+        let mut stats = StatsBuilder::new(method, src_objects.len());
+        let mut items = BuildItem::from_objects(src_objects, time0, time1, threads);
+        let node =
+            BvhNode::build_recursive(&mut items, method, 1, fork_levels(threads), &mut stats);
+        (node, stats.finish())
+    }
+
+    fn build_recursive(
+        items: &mut [BuildItem],
+        method: SplitMethod,
+        depth: usize,
+        forks: usize,
+        stats: &mut StatsBuilder,
     ) -> Self {
-        let objects = &mut src_objects.to_owned();
+        let box1 = surrounding(items);
+        if items.len() <= 2 {
+            stats.node(&box1, depth, items.len());
+            return Self {
+                left: items[0].object.clone(),
+                right: items.get(1).and_then(|item| item.object.clone()),
+                box1,
+            };
+        }
+        stats.node(&box1, depth, 0);
 
-        let axis = random_i32(0, 2);
-        let comparator = if axis == 0 {
-            BvhNode::box_x_compare
-        } else if axis == 1 {
-            BvhNode::box_y_compare
+        let (mid, _) = split(items, method);
+        let (left, right) = items.split_at_mut(mid);
+        let (left, right) = if forks > 0 && right.len() >= PARALLEL_MIN_ITEMS {
+            let mut right_stats = stats.fork();
+            let (left, right) = thread::scope(|s| {
+                let handle = s.spawn(|| {
+                    BvhNode::build_recursive(right, method, depth + 1, forks - 1, &mut right_stats)
+                });
+                let left = BvhNode::build_recursive(left, method, depth + 1, forks - 1, stats);
+                (left, handle.join().unwrap())
+            });
+            stats.merge(right_stats);
+            (left, right)
         } else {
-            BvhNode::box_z_compare
+            (
+                BvhNode::build_recursive(left, method, depth + 1, forks, stats),
+                BvhNode::build_recursive(right, method, depth + 1, forks, stats),
+            )
         };
+        Self {
+            left: Some(Arc::new(left)),
+            right: Some(Arc::new(right)),
+            box1,
+        }
+    }
+}
 
-        let object_span = end - start;
-        let mut tmp = BvhNode::new0();
-        if object_span == 1 {
-            tmp.left = objects[start].clone();
-            tmp.right = objects[start].clone();
-        } else if object_span == 2 {
-            if comparator(&objects[start], &objects[start + 1]) {
-                tmp.left = objects[start].clone();
-                tmp.right = objects[start + 1].clone();
-            } else {
-                tmp.left = objects[start + 1].clone();
-                tmp.right = objects[start].clone();
-            }
-        } else {
-            objects[start..end].sort_by(|a, b| {
-                if comparator(a, b) {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Greater
-                }
-            });
+pub fn surrounding<T>(items: &[BuildItem<T>]) -> Aabb {
+    items
+        .iter()
+        .skip(1)
+        .fold(items[0].bbox.clone(), |acc, item| {
+            Aabb::surrounding_box(&acc, &item.bbox)
+        })
+}
+
+/// Reorders `items` into two groups with `method` and returns the size of the first and
+/// the axis the groups were separated along.
+pub fn split<T>(items: &mut [BuildItem<T>], method: SplitMethod) -> (usize, usize) {
+    match method {
+        SplitMethod::Median => median_split(items),
+        SplitMethod::Sah => sah_split(items).unwrap_or_else(|| median_split(items)),
+    }
+}
+
+/// Sorts by box minimum along a random axis and splits in the middle. The axis is drawn
+/// from a generator seeded by the number of items rather than the thread's one, so the
+/// tree and what scene builders draw after it do not depend on the thread count.
+fn median_split<T>(items: &mut [BuildItem<T>]) -> (usize, usize) {
+    let axis = (Pcg32::new(items.len() as u64, 0).next_u32() % 3) as usize;
+    items.sort_by(|a, b| a.bbox.min()[axis].total_cmp(&b.bbox.min()[axis]));
+    (items.len() / 2, axis)
+}
 
-            let mid = start + object_span / 2;
-            tmp.left = Some(Arc::new(BvhNode::new(objects, start, mid, time0, time1)));
-            tmp.right = Some(Arc::new(BvhNode::new(objects, mid, end, time0, time1)));
+/// Partitions `items` at the cheapest binned SAH split over all three axes and returns the
+/// size of the left part and the axis, or `None` when all centroids coincide.
+fn sah_split<T>(items: &mut [BuildItem<T>]) -> Option<(usize, usize)> {
+    let mut centroid_min = [f64::INFINITY; 3];
+    let mut centroid_max = [f64::NEG_INFINITY; 3];
+    for item in items.iter() {
+        for a in 0..3 {
+            centroid_min[a] = centroid_min[a].min(item.centroid[a]);
+            centroid_max[a] = centroid_max[a].max(item.centroid[a]);
         }
+    }
+    let bin_of = |item: &BuildItem<T>, axis: usize| {
+        let extent = centroid_max[axis] - centroid_min[axis];
+        let b = ((item.centroid[axis] - centroid_min[axis]) / extent * SAH_BINS as f64) as usize;
+        b.min(SAH_BINS - 1)
+    };
 
-        let mut box_left: Aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
-        let mut box_right: Aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
+    // (cost, axis, number of bins on the left)
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroid_max[axis] - centroid_min[axis] <= 0.0 {
+            continue;
+        }
+        let mut counts = [0usize; SAH_BINS];
+        let mut boxes: [Option<Aabb>; SAH_BINS] = Default::default();
+        for item in items.iter() {
+            let b = bin_of(item, axis);
+            counts[b] += 1;
+            boxes[b] = Some(match &boxes[b] {
+                Some(bbox) => Aabb::surrounding_box(bbox, &item.bbox),
+                None => item.bbox.clone(),
+            });
+        }
 
-        if !tmp
-            .left
-            .clone()
-            .unwrap()
-            .bounding_box(time0, time1, &mut box_left)
-            || !tmp
-                .right
-                .clone()
-                .unwrap()
-                .bounding_box(time0, time1, &mut box_right)
-        {
-            eprintln!("No bounding box in bvh_node constructor.\n");
+        // Sweep from the right to get the area and count right of every split.
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc: Option<Aabb> = None;
+        let mut count = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = merge(acc, &boxes[b]);
+            count += counts[b];
+            right_area[b] = acc.as_ref().map_or(0.0, Aabb::surface_area);
+            right_count[b] = count;
         }
 
-        let box2 = Aabb::surrounding_box(&box_left, &box_right);
-        Self {
-            left: tmp.left,
-            right: tmp.right,
-            box1: box2,
+        let mut acc: Option<Aabb> = None;
+        let mut count = 0;
+        for split in 1..SAH_BINS {
+            acc = merge(acc, &boxes[split - 1]);
+            count += counts[split - 1];
+            if count == 0 || right_count[split] == 0 {
+                continue;
+            }
+            let left_area = acc.as_ref().map_or(0.0, Aabb::surface_area);
+            let cost = left_area * count as f64 + right_area[split] * right_count[split] as f64;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (_, axis, split) = best?;
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin_of(&items[i], axis) < split {
+            items.swap(i, mid);
+            mid += 1;
         }
     }
+    Some((mid, axis))
+}
 
-    pub fn new1(list: &mut HittableList, time0: f64, time1: f64) -> Self {
-        let length = list.objects.len();
-        BvhNode::new(&mut list.objects.clone(), 0, length, time0, time1)
+fn merge(acc: Option<Aabb>, bbox: &Option<Aabb>) -> Option<Aabb> {
+    match (acc, bbox) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, b)),
+        (Some(a), None) => Some(a),
+        (None, b) => b.clone(),
     }
 }
 
@@ -144,16 +483,81 @@ impl Hittable for BvhNode {
     }
 
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        if !(*self).clone().box1.hit(r, t_min, t_max) {
+        if !self.box1.hit(r, t_min, t_max) {
             return false;
         }
-        let hit_left = self.left.clone().unwrap().hit(r, t_min, t_max, &mut *rec);
-        let hit_right =
-            self.right
-                .clone()
-                .unwrap()
-                .hit(r, t_min, if hit_left { rec.t } else { t_max }, rec);
+        let hit_left = self.left.as_ref().unwrap().hit(r, t_min, t_max, &mut *rec);
+        let hit_right = match &self.right {
+            Some(right) => right.hit(r, t_min, if hit_left { rec.t } else { t_max }, rec),
+            None => false,
+        };
 
         hit_left || hit_right
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::random_f64_1;
+    use crate::sphere::Sphere;
+    use crate::Vec3;
+
+    /// Clusters of small spheres far apart, where median splits do badly.
+    fn clustered_spheres() -> HittableList {
+        let mut list = HittableList::new();
+        for cluster in 0..4 {
+            let center = Point3::new(cluster as f64 * 100.0, 0.0, (cluster % 2) as f64 * 50.0);
+            let count = if cluster == 0 { 200 } else { 20 };
+            for _ in 0..count {
+                let offset = Vec3::random1(-3.0, 3.0);
+                list.add(Some(Arc::new(Sphere::new(center + offset, 0.2, None))));
+            }
+        }
+        list
+    }
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let list = clustered_spheres();
+        for method in [SplitMethod::Median, SplitMethod::Sah] {
+            let (bvh, stats) = BvhNode::build(&list.objects, method, 0.0, 1.0, 1);
+            assert_eq!(stats.primitives, 260);
+            for _ in 0..2000 {
+                let origin =
+                    Point3::new(random_f64_1(-10.0, 310.0), 20.0, random_f64_1(-10.0, 60.0));
+                let target = Point3::new(random_f64_1(-5.0, 305.0), 0.0, random_f64_1(-5.0, 55.0));
+                let r = Ray::new(origin, target - origin, 0.0);
+                let mut expected = HitRecord::new();
+                let mut actual = HitRecord::new();
+                let hit = list.hit(&r, 0.001, f64::INFINITY, &mut expected);
+                assert_eq!(hit, bvh.hit(&r, 0.001, f64::INFINITY, &mut actual));
+                if hit {
+                    assert!((expected.t - actual.t).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_list() {
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        for layout in [BvhLayout::Tree, BvhLayout::Flat] {
+            let options = BvhOptions {
+                layout,
+                ..BvhOptions::default()
+            };
+            let (bvh, stats) = options.build(&HittableList::new(), 0.0, 1.0);
+            assert_eq!((stats.primitives, stats.nodes), (0, 0));
+            assert!(!bvh.hit(&r, 0.001, f64::INFINITY, &mut HitRecord::new()));
+        }
+    }
+
+    #[test]
+    fn test_sah_beats_median() {
+        let list = clustered_spheres();
+        let (_, median) = BvhNode::build(&list.objects, SplitMethod::Median, 0.0, 1.0, 1);
+        let (_, sah) = BvhNode::build(&list.objects, SplitMethod::Sah, 0.0, 1.0, 1);
+        assert!(sah.sah_cost < median.sah_cost, "{} vs {}", sah, median);
+    }
+}
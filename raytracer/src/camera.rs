@@ -1,6 +1,6 @@
 use crate::{ray, rtweekend, vec3};
 
-use crate::rtweekend::random_f64_1;
+use crate::sampler::next_1d;
 pub use ray::Ray;
 pub use rtweekend::degrees_to_radians;
 pub use vec3::Point3;
@@ -67,7 +67,7 @@ impl Camera {
         Ray::new(
             self.origin + offest,
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offest,
-            random_f64_1(self.time0, self.time1),
+            self.time0 + (self.time1 - self.time0) * next_1d(),
         )
     }
 }
@@ -0,0 +1,209 @@
+use crate::bvh::{BvhLayout, SplitMethod};
+use crate::color::ToneMap;
+use crate::output::OutputFormat;
+use crate::sampler::SamplerKind;
+use crate::scene::SCENES;
+use crate::tiles::TileOrder;
+use clap::builder::{PossibleValuesParser, RangedU64ValueParser};
+use clap::Parser;
+
+/// Command-line options. Image settings left unset fall back to the defaults of the chosen scene.
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about = "A ray tracer following the Ray Tracing in One Weekend books"
+)]
+pub struct Args {
+    /// Built-in scene to render
+    #[arg(short, long, default_value = "final_scene", value_parser = PossibleValuesParser::new(SCENES.map(|(name, _)| name)))]
+    pub scene: String,
+
+    /// Scene description file (.json or .toml) to render instead of a built-in scene
+    #[arg(long, conflicts_with = "scene")]
+    pub scene_file: Option<String>,
+
+    /// Print the built-in scenes and exit
+    #[arg(long)]
+    pub list_scenes: bool,
+
+    /// Image width in pixels
+    #[arg(short, long, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub width: Option<usize>,
+
+    /// Image height in pixels; overrides the aspect ratio when given
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub height: Option<usize>,
+
+    /// Image aspect ratio (width / height)
+    #[arg(long, value_parser = positive_f64)]
+    pub aspect_ratio: Option<f64>,
+
+    /// Samples per pixel
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub spp: Option<i32>,
+
+    /// Stop sampling a pixel once the standard error of its mean luminance falls below this
+    /// fraction of the mean; `--spp` becomes the most samples a pixel gets
+    #[arg(long, value_name = "THRESHOLD", value_parser = positive_f64)]
+    pub adaptive: Option<f64>,
+
+    /// Samples every pixel gets before adaptive sampling judges its noise
+    #[arg(long, default_value_t = 16)]
+    pub min_spp: u32,
+
+    /// Also write an image of how many samples each pixel got
+    #[arg(long, value_name = "PATH")]
+    pub heatmap: Option<String>,
+
+    /// Render in passes of this many samples per pixel, writing the output image after each
+    #[arg(long, value_name = "SPP")]
+    pub progressive: Option<u32>,
+
+    /// In progressive mode, write the image at most this often instead of after every pass
+    #[arg(long, value_name = "SECONDS")]
+    pub snapshot_interval: Option<f64>,
+
+    /// How the random numbers of the samples of a pixel are spread out
+    #[arg(long, value_enum, default_value_t = SamplerKind::Sobol)]
+    pub sampler: SamplerKind,
+
+    /// Number of bounces after which paths are ended by Russian roulette
+    #[arg(long, default_value_t = 5)]
+    pub rr_depth: i32,
+
+    /// Hard cap on the number of bounces; paths are only ended by Russian roulette when unset
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub max_depth: Option<i32>,
+
+    /// How BVHs split their objects
+    #[arg(long, value_enum, default_value_t = SplitMethod::Sah)]
+    pub bvh: SplitMethod,
+
+    /// Memory layout of BVHs
+    #[arg(long, value_enum, default_value_t = BvhLayout::Flat)]
+    pub bvh_layout: BvhLayout,
+
+    /// Number of render threads, defaults to the number of available cores
+    #[arg(short, long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: Option<usize>,
+
+    /// Width and height of the tiles render threads take from the queue, in pixels
+    #[arg(long, default_value_t = 32, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub tile_size: usize,
+
+    /// Order the tiles are rendered in
+    #[arg(long, value_enum, default_value_t = TileOrder::Spiral)]
+    pub tile_order: TileOrder,
+
+    /// Output image path (.jpg, .png, .ppm, .hdr or .exr)
+    #[arg(short, long, default_value = "output/test.jpg")]
+    pub output: String,
+
+    /// Output image format, inferred from the output file extension when unset
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Tone mapping curve for JPEG, PNG and PPM output
+    #[arg(long, value_enum, default_value_t = ToneMap::Clamp)]
+    pub tone_map: ToneMap,
+
+    /// Exposure in stops (EV) applied before tone mapping
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_parser = finite_f64)]
+    pub exposure: f64,
+
+    /// Radiance that maps to white with the reinhard-extended and uncharted2 curves
+    #[arg(long, default_value_t = 11.2, value_parser = positive_f64)]
+    pub white_point: f64,
+
+    /// JPEG quality, from 0 to 100
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub quality: u8,
+
+    /// Seed for the random number generator, making renders repeatable on any number of
+    /// threads; a random seed is picked and printed when unset
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl Args {
+    pub fn print_scenes() {
+        println!("Available scenes:");
+        for (name, description) in SCENES {
+            println!("  {:<20} {}", name, description);
+        }
+    }
+}
+
+/// Parses a finite number.
+fn finite_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        Ok(_) => Err(format!("`{}` is not a finite number", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses a finite number greater than zero.
+fn positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        Ok(_) => Err(format!("`{}` is not a positive number", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_out_of_range_settings() {
+        for arg in [
+            "--width=0",
+            "--width=1",
+            "--height=0",
+            "--height=1",
+            "--aspect-ratio=0",
+            "--aspect-ratio=-1.5",
+            "--aspect-ratio=inf",
+            "--spp=-3",
+            "--spp=0",
+            "--max-depth=0",
+            "--threads=0",
+            "--adaptive=0",
+            "--adaptive=-0.1",
+            "--adaptive=nan",
+            "--exposure=inf",
+            "--exposure=nan",
+            "--white-point=0",
+            "--white-point=-1",
+            "--tile-size=0",
+        ] {
+            let err = Args::try_parse_from(["raytracer", arg]).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                clap::error::ErrorKind::ValueValidation,
+                "{}",
+                arg
+            );
+        }
+        let args = Args::try_parse_from([
+            "raytracer",
+            "--width=10",
+            "--height=5",
+            "--aspect-ratio=1.5",
+            "--spp=1",
+            "--max-depth=1",
+            "--adaptive=0.05",
+            "--exposure=-1.5",
+            "--white-point=4",
+        ])
+        .unwrap();
+        assert_eq!((args.width, args.height), (Some(10), Some(5)));
+        assert_eq!(args.aspect_ratio, Some(1.5));
+        assert_eq!((args.spp, args.max_depth), (Some(1), Some(1)));
+        assert_eq!(args.adaptive, Some(0.05));
+        assert_eq!((args.exposure, args.white_point), (-1.5, 4.0));
+    }
+}
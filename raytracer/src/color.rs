@@ -1,25 +1,148 @@
-use crate::rtweekend;
+//! Display transforms from scene radiance to encoded pixel values.
+
 use crate::vec3::Color1;
-use image::RgbImage;
-use rtweekend::clamp;
-
-/// the multi-sample write_color() function
-pub fn write_color(color: &Color1, img: &mut RgbImage, i: usize, j: usize, samples_per_pixel: i32) {
-    let mut r = color.x;
-    let mut g = color.y;
-    let mut b = color.z;
-
-    let scale = 1.0 / (samples_per_pixel as f64);
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
-
-    let pixel_color = [
-        (clamp(r, 0.0, 0.999) * 256.0) as u8,
-        (clamp(g, 0.0, 0.999) * 256.0) as u8,
-        (clamp(b, 0.0, 0.999) * 256.0) as u8,
-    ];
-    let pixel = img.get_pixel_mut(i.try_into().unwrap(), j.try_into().unwrap());
-    *pixel = image::Rgb(pixel_color);
-    // Write the translated [0,255] value of each color component.
+use clap::ValueEnum;
+
+/// Curve compressing scene radiance into the displayable `[0, 1]` range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMap {
+    /// Linear, everything above 1 is clipped
+    Clamp,
+    /// c / (1 + c)
+    Reinhard,
+    /// Reinhard reaching 1 at the white point
+    ReinhardExtended,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Uncharted2,
+}
+
+impl ToneMap {
+    fn map(self, x: f64, white: f64) -> f64 {
+        match self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::ReinhardExtended => x * (1.0 + x / (white * white)) / (1.0 + x),
+            ToneMap::Aces => {
+                let x = x * 0.6;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }
+            ToneMap::Uncharted2 => uncharted2(2.0 * x) / uncharted2(white),
+        }
+    }
+}
+
+fn uncharted2(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+/// The sRGB opto-electronic transfer function.
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Exposure, then tone mapping, then sRGB encoding.
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayTransform {
+    pub tone_map: ToneMap,
+    /// Exposure in stops; radiance is scaled by `2^exposure`.
+    pub exposure: f64,
+    /// Radiance mapped to 1 by the extended Reinhard and Uncharted 2 curves.
+    pub white_point: f64,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            white_point: 11.2,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Encoded display values in `[0, 1]`.
+    pub fn apply(&self, color: &Color1) -> [f64; 3] {
+        let scale = self.exposure.exp2();
+        [color.x, color.y, color.z].map(|c| {
+            // NaN from a broken sample shows up black instead of poisoning the conversion.
+            let c = if c.is_nan() { 0.0 } else { c.max(0.0) * scale };
+            srgb_oetf(self.tone_map.map(c, self.white_point).clamp(0.0, 1.0))
+        })
+    }
+
+    /// Translates a radiance value into [0,255] for each color component.
+    pub fn encode8(&self, color: &Color1) -> [u8; 3] {
+        self.apply(color).map(|c| (c * 255.0).round() as u8)
+    }
+
+    /// Translates a radiance value into [0,65535] for each color component.
+    pub fn encode16(&self, color: &Color1) -> [u16; 3] {
+        self.apply(color).map(|c| (c * 65535.0).round() as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_maps() {
+        for tone_map in [
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ReinhardExtended,
+            ToneMap::Aces,
+            ToneMap::Uncharted2,
+        ] {
+            let transform = DisplayTransform {
+                tone_map,
+                ..Default::default()
+            };
+            assert_eq!(transform.encode8(&Color1::new(0.0, 0.0, 0.0)), [0, 0, 0]);
+            // Monotonic, and nothing leaves the displayable range.
+            let mut last = 0.0;
+            for i in 1..200 {
+                let value = transform.apply(&Color1::new(i as f64 * 0.1, 0.0, 0.0))[0];
+                assert!(value >= last && value <= 1.0, "{:?} at {}", tone_map, i);
+                last = value;
+            }
+        }
+
+        let extended = DisplayTransform {
+            tone_map: ToneMap::ReinhardExtended,
+            white_point: 4.0,
+            ..Default::default()
+        };
+        assert!((extended.apply(&Color1::new(4.0, 4.0, 4.0))[0] - 1.0).abs() < 1e-12);
+        let uncharted = DisplayTransform {
+            tone_map: ToneMap::Uncharted2,
+            ..Default::default()
+        };
+        assert!((uncharted.apply(&Color1::new(5.6, 5.6, 5.6))[0] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_exposure_and_srgb() {
+        let transform = DisplayTransform::default();
+        assert_eq!(
+            transform.encode8(&Color1::new(1.0, 0.5, 0.22)),
+            [255, 188, 129]
+        );
+        let darker = DisplayTransform {
+            exposure: -1.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            darker.encode8(&Color1::new(2.0, 1.0, 0.44)),
+            [255, 188, 129]
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use crate::aabb::Aabb;
 use crate::material::Isotropic;
-use crate::{random_f64, Color1, HitRecord, Hittable, Material, Ray, Vec3, INFINITY};
+use crate::{random_f64, Color1, HitRecord, Hittable, Material, Ray, Texture, Vec3, INFINITY};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -11,13 +11,17 @@ pub struct ConstantMedium {
 }
 
 impl ConstantMedium {
-    /*    pub fn new(b:Option<Arc<dyn Hittable + Send + Sync>>, d:f64, a:Option<Arc<dyn Texture + Send + Sync>>) -> Self {
-        Self{
-            boundary:b,
-            neg_inv_density:-1.0/d,
-            phase_function:Some(Arc::new(Isotropic::new1(a))),
+    pub fn new(
+        b: Option<Arc<dyn Hittable + Send + Sync>>,
+        d: f64,
+        a: Option<Arc<dyn Texture + Send + Sync>>,
+    ) -> Self {
+        Self {
+            boundary: b,
+            neg_inv_density: -1.0 / d,
+            phase_function: Some(Arc::new(Isotropic::new1(a))),
         }
-    }*/
+    }
 
     pub fn new1(b: Option<Arc<dyn Hittable + Send + Sync>>, d: f64, c: Color1) -> Self {
         Self {
@@ -94,6 +98,9 @@ impl Hittable for ConstantMedium {
         rec.normal = Vec3::new(1.0, 0.0, 0.0);
         rec.front_face = true;
         rec.mat_ptr = (*self).clone().phase_function;
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
+        rec.dpdu = Vec3::new(0.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, 0.0);
 
         true
     }
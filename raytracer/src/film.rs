@@ -0,0 +1,187 @@
+use crate::tiles::Tile;
+use crate::vec3::Color1;
+
+/// Luminance below which `Film::relative_error` measures noise in absolute terms.
+const MIN_LUMINANCE: f64 = 0.01;
+
+/// Radiance samples of one pixel, summed up before they are added to the film.
+#[derive(Copy, Clone, Debug)]
+pub struct PixelSamples {
+    pub sum: Color1,
+    /// Sum of the squared luminance of the samples, for the variance.
+    pub luminance_sq: f64,
+    pub count: u32,
+}
+
+impl PixelSamples {
+    pub fn new() -> Self {
+        Self {
+            sum: Color1::new(0.0, 0.0, 0.0),
+            luminance_sq: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, radiance: Color1) {
+        self.sum += radiance;
+        self.luminance_sq += luminance(&radiance).powi(2);
+        self.count += 1;
+    }
+}
+
+impl Default for PixelSamples {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rec. 709 luminance of a linear color.
+pub fn luminance(c: &Color1) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Accumulated radiance of every pixel, in rows from the top.
+/// Sums are kept as `f32` RGB together with the number of samples they hold and the sum of
+/// their squared luminance.
+#[derive(Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    sums: Vec<[f32; 3]>,
+    luminance_sq: Vec<f32>,
+    samples: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: vec![[0.0; 3]; width * height],
+            luminance_sq: vec![0.0; width * height],
+            samples: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn add_samples(&mut self, x: usize, y: usize, samples: &PixelSamples) {
+        let index = y * self.width + x;
+        let pixel = &mut self.sums[index];
+        pixel[0] += samples.sum.x as f32;
+        pixel[1] += samples.sum.y as f32;
+        pixel[2] += samples.sum.z as f32;
+        self.luminance_sq[index] += samples.luminance_sq as f32;
+        self.samples[index] += samples.count;
+    }
+
+    /// Adds the samples of every pixel of `tile`, given in rows from the top.
+    pub fn add_tile(&mut self, tile: &Tile, samples: &[PixelSamples]) {
+        for (k, pixel) in samples.iter().enumerate() {
+            let x = tile.x0 + k % tile.width();
+            let y = tile.y0 + k / tile.width();
+            self.add_samples(x, y, pixel);
+        }
+    }
+
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.samples[y * self.width + x]
+    }
+
+    /// Standard error of the mean luminance of pixel `(x, y)` relative to that mean, or
+    /// infinity with fewer than two samples or with NaN or infinite samples. Means below
+    /// `MIN_LUMINANCE` count as that much, so that black pixels with a little noise can
+    /// converge.
+    pub fn relative_error(&self, x: usize, y: usize) -> f64 {
+        let index = y * self.width + x;
+        let n = self.samples[index] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let [r, g, b] = self.sums[index];
+        let mean = luminance(&Color1::new(r as f64, g as f64, b as f64)) / n;
+        let luminance_sq = self.luminance_sq[index] as f64;
+        if !(mean.is_finite() && luminance_sq.is_finite()) {
+            return f64::INFINITY;
+        }
+        let variance = ((luminance_sq - n * mean * mean) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(MIN_LUMINANCE)
+    }
+
+    /// Which pixels, in rows from the top, have a `relative_error` above `threshold` or a
+    /// NaN one.
+    pub fn noisy_pixels(&self, threshold: f64) -> Vec<bool> {
+        (0..self.height)
+            .flat_map(|y| {
+                (0..self.width).map(move |x| {
+                    let error = self.relative_error(x, y);
+                    error.is_nan() || error > threshold
+                })
+            })
+            .collect()
+    }
+
+    /// The mean radiance of pixel `(x, y)`, black before any sample arrived.
+    pub fn pixel(&self, x: usize, y: usize) -> Color1 {
+        let index = y * self.width + x;
+        match self.samples[index] {
+            0 => Color1::new(0.0, 0.0, 0.0),
+            n => {
+                let [r, g, b] = self.sums[index];
+                Color1::new(r as f64, g as f64, b as f64) / n as f64
+            }
+        }
+    }
+
+    /// Mean radiance of every pixel, in rows from the top.
+    pub fn pixels(&self) -> impl Iterator<Item = Color1> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.pixel(x, y)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_error() {
+        let mut film = Film::new(2, 1);
+        let mut flat = PixelSamples::new();
+        let mut noisy = PixelSamples::new();
+        for i in 0..100 {
+            flat.add(Color1::new(0.5, 0.5, 0.5));
+            let v = if i % 2 == 0 { 0.0 } else { 1.0 };
+            noisy.add(Color1::new(v, v, v));
+        }
+        film.add_samples(0, 0, &flat);
+        film.add_samples(1, 0, &noisy);
+        assert_eq!(film.samples(1, 0), 100);
+        assert!(film.relative_error(0, 0) < 1e-3);
+        // Standard deviation 0.5 over 100 samples gives a standard error of 0.05 around 0.5.
+        let error = film.relative_error(1, 0);
+        assert!((error - 0.1).abs() < 1e-3, "{}", error);
+        assert!((film.pixel(1, 0).x - 0.5).abs() < 1e-6);
+
+        let mut one = PixelSamples::new();
+        one.add(Color1::new(1.0, 1.0, 1.0));
+        let mut film = Film::new(1, 1);
+        film.add_samples(0, 0, &one);
+        assert_eq!(film.relative_error(0, 0), f64::INFINITY);
+
+        let mut broken = PixelSamples::new();
+        for _ in 0..100 {
+            broken.add(Color1::new(f64::NAN, 0.5, 0.5));
+        }
+        let mut film = Film::new(2, 1);
+        film.add_samples(0, 0, &flat);
+        film.add_samples(1, 0, &broken);
+        assert_eq!(film.relative_error(1, 0), f64::INFINITY);
+        assert_eq!(film.noisy_pixels(0.01), vec![false, true]);
+    }
+}
@@ -2,9 +2,10 @@ use crate::aabb::Aabb;
 use crate::material::Material;
 
 use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vec3::Color1;
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
-use crate::{degrees_to_radians, INFINITY};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -16,6 +17,12 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    /// Interpolated per-vertex color, white for surfaces without one.
+    pub vertex_color: Color1,
+    /// Derivatives of `p` with respect to `u` and `v`: the tangent frame normal and bump
+    /// maps work in. Zero for hits without a surface, like those inside a medium.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
 
 impl Default for HitRecord {
@@ -34,6 +41,9 @@ impl HitRecord {
             mat_ptr: None,
             u: 0.0,
             v: 0.0,
+            vertex_color: Color1::new(1.0, 1.0, 1.0),
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
         }
     }
 
@@ -50,30 +60,51 @@ impl HitRecord {
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool;
+
+    /// Solid-angle density of `random(o)` producing direction `v`; only needed for
+    /// objects used as lights.
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// A random direction from `o` towards the object.
+    fn random(&self, _o: &Point3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
-pub struct Translate {
+/// An object placed by an arbitrary affine transform from its own space into the world.
+pub struct Transformed {
     ptr: Option<Arc<dyn Hittable + Send + Sync>>,
-    offset: Vec3,
+    object_to_world: Transform,
+    world_to_object: Transform,
 }
 
-impl Translate {
-    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, displacement: &Vec3) -> Self {
+impl Transformed {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, transform: Transform) -> Self {
         Self {
             ptr: p,
-            offset: *displacement,
+            object_to_world: transform,
+            world_to_object: transform.inverse(),
         }
     }
 }
 
-impl Hittable for Translate {
+impl Hittable for Transformed {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let moved_r = Ray::new(r.orig - self.offset, r.direction(), r.time());
-        if !self.ptr.clone().unwrap().hit(&moved_r, t_min, t_max, rec) {
+        // The direction is not renormalized, so `t` is the same in both spaces.
+        let object_r = Ray::new(
+            self.world_to_object.point(&r.origin()),
+            self.world_to_object.vector(&r.direction()),
+            r.time(),
+        );
+        if !self.ptr.clone().unwrap().hit(&object_r, t_min, t_max, rec) {
             return false;
         }
-        rec.p += self.offset;
-        rec.clone().set_face_normal(&moved_r, &rec.normal);
+        rec.p = self.object_to_world.point(&rec.p);
+        rec.normal = self.object_to_world.normal(&rec.normal).unit_vector();
+        rec.dpdu = self.object_to_world.vector(&rec.dpdu);
+        rec.dpdv = self.object_to_world.vector(&rec.dpdv);
 
         true
     }
@@ -87,101 +118,105 @@ impl Hittable for Translate {
         {
             return false;
         }
-        *output_box = Aabb::new(
-            output_box.min() + self.offset,
-            output_box.max() + self.offset,
-        );
+        *output_box = self.object_to_world.bounding_box(output_box);
 
         true
     }
-}
-
-#[derive(Clone)]
-pub struct RotateY {
-    ptr: Option<Arc<dyn Hittable + Send + Sync>>,
-    sin_theta: f64,
-    cos_theta: f64,
-    hasbox: bool,
-    bbox: Aabb,
-}
-
-impl RotateY {
-    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, angle: f64) -> Self {
-        let radians = degrees_to_radians(angle);
-        let sin_theta1 = radians.sin();
-        let cos_theta1 = radians.cos();
-        let mut bbox1 = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
-        let hasbox1 = p.clone().unwrap().bounding_box(0.0, 1.0, &mut bbox1);
-
-        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
-        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox1.max().x + (1.0 - i as f64) * bbox1.min().x;
-                    let y = j as f64 * bbox1.max().y + (1.0 - j as f64) * bbox1.min().y;
-                    let z = k as f64 * bbox1.max().z + (1.0 - k as f64) * bbox1.min().z;
-
-                    let newx = cos_theta1 * x + sin_theta1 * z;
-                    let newz = -sin_theta1 * x + cos_theta1 * z;
-
-                    let tester = Vec3::new(newx, y, newz);
-
-                    min.x = min.x.min(tester.x);
-                    max.x = max.x.max(tester.x);
-                    min.y = min.y.min(tester.y);
-                    max.y = max.y.max(tester.y);
-                    min.z = min.z.min(tester.z);
-                    max.z = max.z.max(tester.z);
-                }
-            }
-        }
 
-        Self {
-            ptr: p.clone(),
-            sin_theta: sin_theta1,
-            cos_theta: cos_theta1,
-            hasbox: hasbox1,
-            bbox: Aabb::new(min, max),
-        }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let object_v = self.world_to_object.vector(v);
+        let pdf = self
+            .ptr
+            .clone()
+            .unwrap()
+            .pdf_value(&self.world_to_object.point(o), &object_v);
+        // A direction w in object space maps to A w in the world; the solid angle around it
+        // grows by |det A| / |A w|^3.
+        let stretch = self
+            .object_to_world
+            .vector(&object_v.unit_vector())
+            .length();
+        pdf * stretch.powi(3) / self.object_to_world.determinant().abs()
     }
-}
 
-impl Hittable for RotateY {
-    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
-        *output_box = (*self).clone().bbox;
-        self.hasbox
+    fn random(&self, o: &Point3) -> Vec3 {
+        let object_o = self.world_to_object.point(o);
+        self.object_to_world
+            .vector(&self.ptr.clone().unwrap().random(&object_o))
     }
+}
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut origin = r.orig;
-        let mut direction = r.direction();
+/// Declares a named convenience wrapper around `Transformed`.
+macro_rules! transformed_wrapper {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        pub struct $name(Transformed);
 
-        origin.x = self.cos_theta * r.orig[0] - self.sin_theta * r.orig[2];
-        origin.z = self.sin_theta * r.orig[0] + self.cos_theta * r.orig[2];
+        impl Hittable for $name {
+            fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+                self.0.hit(r, t_min, t_max, rec)
+            }
 
-        direction.x = self.cos_theta * r.direction()[0] - self.sin_theta * r.direction()[2];
-        direction.z = self.sin_theta * r.direction()[0] + self.cos_theta * r.direction()[2];
+            fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+                self.0.bounding_box(time0, time1, output_box)
+            }
 
-        let rotated_r = Ray::new(origin, direction, r.time());
+            fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+                self.0.pdf_value(o, v)
+            }
 
-        if !self.ptr.clone().unwrap().hit(&rotated_r, t_min, t_max, rec) {
-            return false;
+            fn random(&self, o: &Point3) -> Vec3 {
+                self.0.random(o)
+            }
         }
+    };
+}
+
+transformed_wrapper!(Translate);
+transformed_wrapper!(
+    /// Rotation around the x axis, in degrees.
+    RotateX
+);
+transformed_wrapper!(
+    /// Rotation around the y axis, in degrees.
+    RotateY
+);
+transformed_wrapper!(
+    /// Rotation around the z axis, in degrees.
+    RotateZ
+);
+transformed_wrapper!(
+    /// Non-uniform scale about the origin.
+    Scale
+);
 
-        let mut p = rec.p;
-        let mut normal = rec.normal;
+impl Translate {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, displacement: &Vec3) -> Self {
+        Self(Transformed::new(p, Transform::translate(displacement)))
+    }
+}
 
-        p.x = self.cos_theta * rec.p[0] + self.sin_theta * rec.p[2];
-        p.z = -self.sin_theta * rec.p[0] + self.cos_theta * rec.p[2];
+impl RotateX {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, angle: f64) -> Self {
+        Self(Transformed::new(p, Transform::rotate_x(angle)))
+    }
+}
 
-        normal.x = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
-        normal.z = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
+impl RotateY {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, angle: f64) -> Self {
+        Self(Transformed::new(p, Transform::rotate_y(angle)))
+    }
+}
 
-        rec.p = p;
-        rec.set_face_normal(&rotated_r, &normal);
+impl RotateZ {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, angle: f64) -> Self {
+        Self(Transformed::new(p, Transform::rotate_z(angle)))
+    }
+}
 
-        true
+impl Scale {
+    /// `None` when a factor is zero.
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, factors: &Vec3) -> Option<Self> {
+        Some(Self(Transformed::new(p, Transform::scale(factors)?)))
     }
 }
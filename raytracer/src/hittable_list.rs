@@ -1,10 +1,9 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 pub use crate::ray::Ray;
-pub use crate::sphere::Sphere;
-use crate::Point3;
+use crate::rtweekend::random_i32;
+use crate::{Point3, Vec3};
 pub use std::sync::Arc;
-pub use std::vec;
 
 #[derive(Clone)]
 pub struct HittableList {
@@ -31,8 +30,10 @@ impl HittableList {
     pub fn add(&mut self, object: Option<Arc<dyn Hittable + Send + Sync>>) {
         self.objects.push(object);
     }
+}
 
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut temp_rec = HitRecord::new();
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
@@ -51,7 +52,7 @@ impl HittableList {
         hit_anything
     }
 
-    pub fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
         if self.objects.is_empty() {
             return false;
         }
@@ -76,4 +77,17 @@ impl HittableList {
         }
         true
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.clone().unwrap().pdf_value(o, v))
+            .sum()
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        let index = random_i32(0, self.objects.len() as i32 - 1) as usize;
+        self.objects[index].clone().unwrap().random(o)
+    }
 }
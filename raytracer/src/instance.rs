@@ -0,0 +1,112 @@
+use crate::aabb::Aabb;
+use crate::hittable::Transformed;
+use crate::material::Material;
+use crate::transform::Transform;
+use crate::{HitRecord, Hittable, Point3, Ray, Vec3};
+use std::sync::Arc;
+
+/// One placement of a shared prototype, usually a BVH built once with `BvhBuilder`. Instances
+/// only hold a reference to the prototype, a transform and an optional material, so a BVH
+/// over many instances stays small however large the prototype is.
+pub struct Instance {
+    object: Transformed,
+    /// Replaces the materials of the prototype when set.
+    mat_ptr: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl Instance {
+    pub fn new(
+        prototype: Option<Arc<dyn Hittable + Send + Sync>>,
+        transform: Transform,
+        m: Option<Arc<dyn Material + Send + Sync>>,
+    ) -> Self {
+        Self {
+            object: Transformed::new(prototype, transform),
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.object.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        if self.mat_ptr.is_some() {
+            rec.mat_ptr = self.mat_ptr.clone();
+        }
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.object.bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.object.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        self.object.random(o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BvhOptions;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color1;
+    use crate::{HittableList, INFINITY};
+
+    #[test]
+    fn test_instances_share_prototype() {
+        let white: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
+        let red: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(Lambertian::new(&Color1::new(0.65, 0.05, 0.05))));
+        let mut spheres = HittableList::new();
+        for i in 0..10 {
+            spheres.add(Some(Arc::new(Sphere::new(
+                Point3::new(i as f64, 0.0, 0.0),
+                0.25,
+                white.clone(),
+            ))));
+        }
+        let (prototype, _) = BvhOptions::default().build(&spheres, 0.0, 1.0);
+
+        let mut instances = HittableList::new();
+        for i in 0..100 {
+            let transform = Transform::translate(&Vec3::new(0.0, 0.0, 20.0 * i as f64))
+                * Transform::rotate_y(90.0 * (i % 2) as f64);
+            let material = if i == 7 { red.clone() } else { None };
+            instances.add(Some(Arc::new(Instance::new(
+                Some(prototype.clone()),
+                transform,
+                material,
+            ))));
+        }
+        assert_eq!(Arc::strong_count(&prototype), 101);
+        let (top, _) = BvhOptions::default().build(&instances, 0.0, 1.0);
+
+        // Instance 7 is rotated, so its spheres run along -z from z = 140.
+        let r = Ray::new(Point3::new(0.0, 5.0, 138.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let mut rec = HitRecord::new();
+        assert!(top.hit(&r, 0.001, INFINITY, &mut rec));
+        assert!((rec.p - Point3::new(0.0, 0.25, 138.0)).length() < 1e-9);
+        assert!(Arc::ptr_eq(
+            rec.mat_ptr.as_ref().unwrap(),
+            red.as_ref().unwrap()
+        ));
+
+        // Instance 8 is not rotated and keeps the prototype's material.
+        let r = Ray::new(Point3::new(3.0, 5.0, 160.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(top.hit(&r, 0.001, INFINITY, &mut rec));
+        assert!((rec.p - Point3::new(3.0, 0.25, 160.0)).length() < 1e-9);
+        assert!(Arc::ptr_eq(
+            rec.mat_ptr.as_ref().unwrap(),
+            white.as_ref().unwrap()
+        ));
+    }
+}
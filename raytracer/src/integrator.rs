@@ -0,0 +1,223 @@
+use crate::material::ScatterRecord;
+use crate::pdf::{power_heuristic, HittablePdf, Pdf};
+use crate::rtweekend::random_f64;
+use crate::sampler;
+use crate::{Color1, HitRecord, Hittable, HittableList, Ray, Vec3, INFINITY};
+use std::sync::Arc;
+
+/// Highest probability of continuing a path under Russian roulette, so that even paths
+/// that lose no energy, like those trapped between mirrors, end eventually.
+const MAX_SURVIVAL: f64 = 0.95;
+
+/// Unidirectional path tracer with next-event estimation: every non-specular hit samples one
+/// of `lights` directly and weights it against the BSDF-sampled bounce with the power
+/// heuristic. Paths end by Russian roulette on their throughput once they are `rr_depth`
+/// bounces long, or at `max_depth` bounces when a hard cap is set.
+pub struct PathTracer {
+    pub world: Arc<HittableList>,
+    pub lights: Arc<HittableList>,
+    pub background: Color1,
+    pub rr_depth: i32,
+    pub max_depth: Option<i32>,
+}
+
+impl PathTracer {
+    pub fn ray_color(&self, r: &Ray) -> Color1 {
+        let mut color = Color1::new(0.0, 0.0, 0.0);
+        let mut throughput = Color1::new(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        // Density the previous bounce chose `ray` with; `None` for camera rays and after
+        // specular bounces, whose emission is not importance sampled by the lights.
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut depth = 0;
+
+        loop {
+            sampler::start_bounce(depth);
+            if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                break;
+            }
+            let mut rec = HitRecord::new();
+            if !self.world.hit(&ray, 0.001, INFINITY, &mut rec) {
+                color += Vec3::elemul(throughput, self.background);
+                break;
+            }
+
+            let mut srec = ScatterRecord::new();
+            let material = rec.mat_ptr.clone().unwrap();
+            let mut emitted = material.emitted(rec.u, rec.v, &rec.p);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                let light_pdf = self.lights.pdf_value(&ray.origin(), &ray.direction());
+                emitted *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+            color += Vec3::elemul(throughput, emitted);
+            if !material.scatter(&ray, &mut rec, &mut srec) {
+                break;
+            }
+
+            if srec.is_specular {
+                throughput = Vec3::elemul(throughput, srec.attenuation);
+                ray = srec.specular_ray;
+                bsdf_pdf = None;
+            } else {
+                if !self.lights.objects.is_empty() {
+                    let direct = self.sample_light(&ray, &rec, &srec);
+                    color += Vec3::elemul(throughput, direct);
+                }
+
+                let pdf = srec.pdf_ptr.clone().unwrap();
+                let scattered = Ray::new(rec.p, pdf.generate(), ray.time());
+                let pdf_val = pdf.value(&scattered.direction());
+                if pdf_val <= 0.0 {
+                    break;
+                }
+                let f = material.eval(&ray, &rec, &srec, &scattered);
+                throughput = Vec3::elemul(throughput, f) / pdf_val;
+                ray = scattered;
+                bsdf_pdf = if self.lights.objects.is_empty() {
+                    None
+                } else {
+                    Some(pdf_val)
+                };
+            }
+
+            depth += 1;
+            if depth >= self.rr_depth {
+                let survival = throughput
+                    .x
+                    .max(throughput.y)
+                    .max(throughput.z)
+                    .min(MAX_SURVIVAL);
+                if random_f64() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        color
+    }
+
+    /// Light reaching `rec` straight from one sampled point on `lights`, MIS-weighted.
+    fn sample_light(&self, r: &Ray, rec: &HitRecord, srec: &ScatterRecord) -> Color1 {
+        let black = Color1::new(0.0, 0.0, 0.0);
+        let light_pdf = HittablePdf::new(Some(self.lights.clone()), &rec.p);
+        let shadow_ray = Ray::new(rec.p, light_pdf.generate(), r.time());
+        let light_pdf_val = light_pdf.value(&shadow_ray.direction());
+        let material = rec.mat_ptr.clone().unwrap();
+        let f = material.eval(r, rec, srec, &shadow_ray);
+        if light_pdf_val <= 0.0 || f.x.max(f.y).max(f.z) <= 0.0 {
+            return black;
+        }
+
+        let mut light_rec = HitRecord::new();
+        if !self.world.hit(&shadow_ray, 0.001, INFINITY, &mut light_rec) {
+            return black;
+        }
+        let light = light_rec.mat_ptr.clone().unwrap();
+        let emitted = light.emitted(light_rec.u, light_rec.v, &light_rec.p);
+        let bsdf_pdf_val = srec.pdf_ptr.clone().unwrap().value(&shadow_ray.direction());
+        let weight = power_heuristic(light_pdf_val, bsdf_pdf_val);
+        Vec3::elemul(f, emitted) * (weight / light_pdf_val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XzRect;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::sphere::Sphere;
+    use crate::{Material, Point3};
+
+    /// A gray floor under a square light, seen straight down from below the light.
+    /// The floor radiance is albedo * L * F with F the form factor to the light.
+    fn floor_under_light() -> (HittableList, HittableList) {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(XzRect::new(
+            -100.0,
+            100.0,
+            -100.0,
+            100.0,
+            0.0,
+            Some(Arc::new(Lambertian::new(&Color1::new(0.5, 0.5, 0.5)))),
+        ))));
+        world.add(Some(Arc::new(XzRect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            2.0,
+            Some(Arc::new(DiffuseLight::new1(Color1::new(4.0, 4.0, 4.0)))),
+        ))));
+        let mut lights = HittableList::new();
+        lights.add(Some(Arc::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 2.0, None))));
+        (world, lights)
+    }
+
+    fn mean(tracer: &PathTracer, r: &Ray, n: usize) -> f64 {
+        (0..n).map(|_| tracer.ray_color(r).x).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn test_light_sampling_unbiased() {
+        let (world, lights) = floor_under_light();
+        let world = Arc::new(world);
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let expected = 0.478_913;
+        for lights in [HittableList::new(), lights] {
+            let tracer = PathTracer {
+                world: world.clone(),
+                lights: Arc::new(lights),
+                background: Color1::new(0.0, 0.0, 0.0),
+                rr_depth: 5,
+                max_depth: Some(5),
+            };
+            let mean = mean(&tracer, &r, 20_000);
+            assert!((mean - expected).abs() < 0.03, "{} vs {}", mean, expected);
+        }
+    }
+
+    /// A diffuse surface that also glows with unit radiance.
+    struct Glowing(Lambertian);
+
+    impl Material for Glowing {
+        fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color1 {
+            Color1::new(1.0, 1.0, 1.0)
+        }
+
+        fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+            self.0.scatter(r_in, rec, srec)
+        }
+
+        fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+            self.0.scattering_pdf(r_in, rec, scattered)
+        }
+    }
+
+    /// Inside a glowing furnace of albedo 0.8 the radiance is 1 + 0.8 + 0.8^2 + ... = 5,
+    /// which only unbounded paths reach.
+    #[test]
+    fn test_russian_roulette_unbiased() {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            10.0,
+            Some(Arc::new(Glowing(Lambertian::new(&Color1::new(
+                0.8, 0.8, 0.8,
+            ))))),
+        ))));
+        let mut tracer = PathTracer {
+            world: Arc::new(world),
+            lights: Arc::new(HittableList::new()),
+            background: Color1::new(0.0, 0.0, 0.0),
+            rr_depth: 3,
+            max_depth: None,
+        };
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let mean_rr = mean(&tracer, &r, 20_000);
+        assert!((mean_rr - 5.0).abs() < 0.1, "{}", mean_rr);
+
+        tracer.max_depth = Some(5);
+        let capped = mean(&tracer, &r, 20_000);
+        assert!(capped < 3.5, "{}", capped);
+    }
+}
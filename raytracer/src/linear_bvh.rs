@@ -0,0 +1,389 @@
+use crate::aabb::Aabb;
+use crate::bvh::{
+    fork_levels, split, surrounding, BuildItem, BvhStats, SplitMethod, StatsBuilder,
+    PARALLEL_MIN_ITEMS,
+};
+use crate::{HitRecord, Hittable, Point3, Ray};
+use std::sync::Arc;
+use std::thread;
+
+/// Deepest a `LinearBvh` gets; the traversal stack has this many entries, and the builder
+/// makes a leaf of whatever is left at this depth.
+const MAX_DEPTH: usize = 64;
+
+/// One node of a `LinearBvh`. The first child of an interior node directly follows it, so
+/// only the second one is stored.
+#[derive(Debug, PartialEq)]
+struct LinearNode {
+    minimum: [f64; 3],
+    maximum: [f64; 3],
+    /// Index of the first primitive for leaves, of the second child for interior nodes.
+    offset: u32,
+    /// Number of primitives, 0 for interior nodes.
+    count: u32,
+    /// Axis the children were split along; rays heading down it visit the first child first.
+    axis: u8,
+}
+
+/// A BVH flattened into one depth-first array of nodes, with primitives referenced by index.
+/// It has the same shape as a `BvhNode` tree built with the same split method, but is
+/// traversed with a stack, visiting the near child first and skipping nodes behind the
+/// closest hit so far.
+pub struct LinearBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl LinearBvh {
+    /// Builds a BVH over `src_objects` on up to `threads` threads; the node array does not
+    /// depend on the number of threads.
+    pub fn build(
+        src_objects: &[Option<Arc<dyn Hittable + Send + Sync>>],
+        method: SplitMethod,
+        time0: f64,
+        time1: f64,
+        threads: usize,
+    ) -> (Self, BvhStats) {
+        let mut stats = StatsBuilder::new(method, src_objects.len());
+        let mut items = BuildItem::from_objects(src_objects, time0, time1, threads);
+        let mut bvh = Self::with_capacity(items.len());
+        bvh.build_recursive(&mut items, method, 1, fork_levels(threads), &mut stats);
+        (bvh, stats.finish())
+    }
+
+    fn with_capacity(primitives: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(2 * primitives),
+            primitives: Vec::with_capacity(primitives),
+        }
+    }
+
+    fn build_recursive(
+        &mut self,
+        items: &mut [BuildItem],
+        method: SplitMethod,
+        depth: usize,
+        forks: usize,
+        stats: &mut StatsBuilder,
+    ) {
+        let bbox = surrounding(items);
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            minimum: [bbox.min().x(), bbox.min().y(), bbox.min().z()],
+            maximum: [bbox.max().x(), bbox.max().y(), bbox.max().z()],
+            offset: self.primitives.len() as u32,
+            count: 0,
+            axis: 0,
+        });
+
+        if items.len() <= 2 || depth == MAX_DEPTH {
+            stats.node(&bbox, depth, items.len());
+            self.nodes[index].count = items.len() as u32;
+            self.primitives
+                .extend(items.iter().map(|item| item.object.clone().unwrap()));
+            return;
+        }
+        stats.node(&bbox, depth, 0);
+
+        let (mid, axis) = split(items, method);
+        self.nodes[index].axis = axis as u8;
+        let (left, right) = items.split_at_mut(mid);
+        if forks > 0 && right.len() >= PARALLEL_MIN_ITEMS {
+            // Build the second subtree into its own arrays and append them afterwards, which
+            // gives the same depth-first order as building it here.
+            let mut right_stats = stats.fork();
+            let mut right_bvh = Self::with_capacity(right.len());
+            thread::scope(|s| {
+                s.spawn(|| {
+                    right_bvh.build_recursive(right, method, depth + 1, forks - 1, &mut right_stats)
+                });
+                self.build_recursive(left, method, depth + 1, forks - 1, stats);
+            });
+            stats.merge(right_stats);
+            self.nodes[index].offset = self.nodes.len() as u32;
+            self.append(right_bvh);
+        } else {
+            self.build_recursive(left, method, depth + 1, forks, stats);
+            self.nodes[index].offset = self.nodes.len() as u32;
+            self.build_recursive(right, method, depth + 1, forks, stats);
+        }
+    }
+
+    /// Moves the nodes and primitives of `other` to the end of `self`.
+    fn append(&mut self, other: LinearBvh) {
+        let node_base = self.nodes.len() as u32;
+        let primitive_base = self.primitives.len() as u32;
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.offset += if node.count > 0 {
+                primitive_base
+            } else {
+                node_base
+            };
+            node
+        }));
+        self.primitives.extend(other.primitives);
+    }
+}
+
+impl LinearNode {
+    fn hit(&self, origin: &[f64; 3], inv_dir: &[f64; 3], t_min: f64, t_max: f64) -> bool {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+        for a in 0..3 {
+            let t0 = (self.minimum[a] - origin[a]) * inv_dir[a];
+            let t1 = (self.maximum[a] - origin[a]) * inv_dir[a];
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let d = r.direction();
+        let o = r.origin();
+        let origin = [o.x(), o.y(), o.z()];
+        let inv_dir = [1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z()];
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+
+        let mut stack = [0u32; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if node.hit(&origin, &inv_dir, t_min, closest_so_far) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for primitive in &self.primitives[first..first + node.count as usize] {
+                        if primitive.hit(r, t_min, closest_so_far, rec) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side of the split axis first.
+                    let (near, far) = if inv_dir[node.axis as usize] < 0.0 {
+                        (node.offset, index as u32 + 1)
+                    } else {
+                        (index as u32 + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near as usize;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len] as usize;
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        let root = &self.nodes[0];
+        *output_box = Aabb::new(
+            Point3::new(root.minimum[0], root.minimum[1], root.minimum[2]),
+            Point3::new(root.maximum[0], root.maximum[1], root.maximum[2]),
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::{BvhLayout, BvhNode, BvhOptions};
+    use crate::rtweekend::{random_f64, random_f64_1, seed_rng};
+    use crate::scene::build_scene;
+    use crate::sphere::Sphere;
+    use crate::{HittableList, Vec3};
+    use std::time::Instant;
+
+    #[test]
+    fn test_linear_bvh_matches_tree() {
+        let mut list = HittableList::new();
+        for _ in 0..500 {
+            let center = Vec3::random1(-20.0, 20.0);
+            list.add(Some(Arc::new(Sphere::new(
+                center,
+                random_f64_1(0.1, 2.0),
+                None,
+            ))));
+        }
+        for method in [SplitMethod::Median, SplitMethod::Sah] {
+            let (tree, _) = BvhNode::build(&list.objects, method, 0.0, 1.0, 1);
+            let (flat, stats) = LinearBvh::build(&list.objects, method, 0.0, 1.0, 1);
+            assert_eq!(stats.primitives, 500);
+            assert_eq!(flat.primitives.len(), 500);
+            for _ in 0..2000 {
+                let origin = Vec3::random1(-30.0, 30.0);
+                let r = Ray::new(origin, Vec3::random_unit_vector(), 0.0);
+                let mut expected = HitRecord::new();
+                let mut actual = HitRecord::new();
+                let hit = list.hit(&r, 0.001, f64::INFINITY, &mut expected);
+                assert_eq!(
+                    hit,
+                    tree.hit(&r, 0.001, f64::INFINITY, &mut HitRecord::new())
+                );
+                assert_eq!(hit, flat.hit(&r, 0.001, f64::INFINITY, &mut actual));
+                if hit {
+                    assert!((expected.t - actual.t).abs() < 1e-9);
+                    assert_eq!(expected.p, actual.p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_build_matches_serial() {
+        let mut list = HittableList::new();
+        for _ in 0..3 * PARALLEL_MIN_ITEMS {
+            let center = Vec3::random1(-100.0, 100.0);
+            list.add(Some(Arc::new(Sphere::new(center, 0.5, None))));
+        }
+        let (serial, serial_stats) = LinearBvh::build(&list.objects, SplitMethod::Sah, 0.0, 1.0, 1);
+        let (parallel, parallel_stats) =
+            LinearBvh::build(&list.objects, SplitMethod::Sah, 0.0, 1.0, 4);
+        assert!(serial.nodes == parallel.nodes);
+        assert!(serial
+            .primitives
+            .iter()
+            .zip(&parallel.primitives)
+            .all(|(a, b)| Arc::ptr_eq(a, b)));
+        assert_eq!(serial_stats.nodes, parallel_stats.nodes);
+        assert_eq!(serial_stats.max_depth, parallel_stats.max_depth);
+        assert!((serial_stats.sah_cost - parallel_stats.sah_cost).abs() < 1e-9);
+
+        let (tree, _) = BvhNode::build(&list.objects, SplitMethod::Sah, 0.0, 1.0, 4);
+        for _ in 0..1000 {
+            let r = Ray::new(
+                Vec3::random1(-120.0, 120.0),
+                Vec3::random_unit_vector(),
+                0.0,
+            );
+            let mut expected = HitRecord::new();
+            let mut actual = HitRecord::new();
+            let hit = serial.hit(&r, 0.001, f64::INFINITY, &mut expected);
+            assert_eq!(hit, tree.hit(&r, 0.001, f64::INFINITY, &mut actual));
+            if hit {
+                assert_eq!(expected.t, actual.t);
+            }
+        }
+    }
+
+    /// Range of `bvh.primitives` under the node at `index`.
+    fn primitive_range(bvh: &LinearBvh, index: usize) -> (usize, usize) {
+        let node = &bvh.nodes[index];
+        if node.count > 0 {
+            let first = node.offset as usize;
+            return (first, first + node.count as usize);
+        }
+        let (first, _) = primitive_range(bvh, index + 1);
+        let (_, last) = primitive_range(bvh, node.offset as usize);
+        (first, last)
+    }
+
+    #[test]
+    fn test_node_axis_is_split_axis() {
+        // Long thin clusters along y make the split axis differ from the longest extent.
+        let mut list = HittableList::new();
+        for i in 0..400 {
+            let x = if i % 2 == 0 { -3.0 } else { 3.0 };
+            let center = Vec3::new(x, random_f64_1(-50.0, 50.0), random_f64_1(-1.0, 1.0));
+            list.add(Some(Arc::new(Sphere::new(center, 0.5, None))));
+        }
+        let (bvh, _) = LinearBvh::build(&list.objects, SplitMethod::Sah, 0.0, 1.0, 1);
+        let centroid = |primitive: &Arc<dyn Hittable + Send + Sync>| {
+            let mut bbox = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
+            primitive.bounding_box(0.0, 1.0, &mut bbox);
+            (bbox.min() + bbox.max()) * 0.5
+        };
+        assert_eq!(bvh.nodes[0].axis, 0);
+        for (index, node) in bvh.nodes.iter().enumerate() {
+            if node.count > 0 {
+                continue;
+            }
+            let axis = node.axis as usize;
+            let (first, _) = primitive_range(&bvh, index + 1);
+            let (mid, last) = primitive_range(&bvh, node.offset as usize);
+            let left_max = bvh.primitives[first..mid]
+                .iter()
+                .map(|p| centroid(p)[axis])
+                .fold(f64::NEG_INFINITY, f64::max);
+            let right_min = bvh.primitives[mid..last]
+                .iter()
+                .map(|p| centroid(p)[axis])
+                .fold(f64::INFINITY, f64::min);
+            assert!(left_max <= right_min, "node {} axis {}", index, axis);
+        }
+    }
+
+    #[test]
+    fn test_leaf_count_fits_more_than_u16() {
+        let mut list = HittableList::new();
+        for _ in 0..70_000 {
+            list.add(Some(Arc::new(Sphere::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                None,
+            ))));
+        }
+        let mut items = BuildItem::from_objects(&list.objects, 0.0, 1.0, 1);
+        let mut stats = StatsBuilder::new(SplitMethod::Sah, items.len());
+        let mut bvh = LinearBvh::with_capacity(items.len());
+        bvh.build_recursive(&mut items, SplitMethod::Sah, MAX_DEPTH, 0, &mut stats);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert_eq!(bvh.nodes[0].count, 70_000);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(bvh.hit(&r, 0.001, f64::INFINITY, &mut HitRecord::new()));
+    }
+
+    /// Times first hits of camera rays through both layouts:
+    /// `cargo test --release -- --ignored --nocapture bench_bvh_layouts`
+    #[test]
+    #[ignore]
+    fn bench_bvh_layouts() {
+        // Scenes load textures like earthmap.jpg relative to the repository root.
+        std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+        for name in ["random_scene", "final_scene"] {
+            for layout in [BvhLayout::Tree, BvhLayout::Flat] {
+                seed_rng(1);
+                let options = BvhOptions {
+                    layout,
+                    ..BvhOptions::default()
+                };
+                let scene = build_scene(name, options).unwrap();
+                let camera = scene.camera.build(scene.aspect_ratio);
+                let rays: Vec<Ray> = (0..500_000)
+                    .map(|_| camera.get_ray(random_f64(), random_f64()))
+                    .collect();
+                let start = Instant::now();
+                let hits = rays
+                    .iter()
+                    .filter(|r| {
+                        scene
+                            .world
+                            .hit(r, 0.001, f64::INFINITY, &mut HitRecord::new())
+                    })
+                    .count();
+                let elapsed = start.elapsed();
+                println!(
+                    "{} {:?}: {} rays, {} hits, {:?} ({:.2} Mrays/s)",
+                    name,
+                    layout,
+                    rays.len(),
+                    hits,
+                    elapsed,
+                    rays.len() as f64 / elapsed.as_secs_f64() / 1e6
+                );
+            }
+        }
+    }
+}
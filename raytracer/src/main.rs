@@ -1,59 +1,79 @@
 const INFINITY: f64 = f64::INFINITY;
 use std::sync::Mutex;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 mod aabb;
 mod aarect;
 mod r#box;
 mod bvh;
 mod camera;
+mod cli;
 mod color;
 mod constant_medium;
+mod film;
 mod hittable;
 mod hittable_list;
+mod instance;
+mod integrator;
+mod linear_bvh;
 mod material;
+mod mesh;
+mod microfacet;
 mod moving_sphere;
+mod obj;
+mod onb;
+mod output;
+mod pdf;
 mod perlin;
+mod ply;
 mod ray;
+mod render;
 mod rtweekend;
+mod sampler;
+mod scene;
+mod scene_file;
 mod sphere;
 mod texture;
+mod tiles;
+mod transform;
+mod triangle;
 mod vec3;
 
 pub use crate::rtweekend::random_f64;
-use crate::rtweekend::random_f64_1;
-use crate::sphere::Sphere;
-use color::write_color;
+use crate::rtweekend::seed_rng;
+use bvh::BvhOptions;
+use clap::Parser;
+use cli::Args;
+use color::DisplayTransform;
+use film::Film;
+use integrator::PathTracer;
+use render::{RenderPlan, Renderer};
+use tiles::make_tiles;
 
-use image::ImageBuffer;
 use indicatif::ProgressBar;
 pub use rtweekend::degrees_to_radians;
 
-use std::fs::File;
+use image::ImageError;
+use output::{save_heatmap, save_image, OutputFormat};
+use std::fs;
+use std::io;
+use std::path::Path;
 
-use crate::aarect::{XyRect, XzRect, YzRect};
-use crate::hittable::{RotateY, Translate};
-use crate::material::DiffuseLight;
-use crate::r#box::Box1;
-use crate::texture::ImageTecture;
 pub use camera::Camera;
 
 pub use hittable::HitRecord;
 pub use hittable::Hittable;
 pub use hittable_list::HittableList;
 
+pub use material::Conductor;
 pub use material::Dielectric;
 pub use material::Lambertian;
 pub use material::Material;
-pub use material::Medal;
 pub use moving_sphere::MovingSphere;
 pub use ray::Ray;
 pub use std::sync::Arc;
-pub use std::vec;
 
-use crate::bvh::BvhNode;
-use crate::constant_medium::ConstantMedium;
 pub use texture::CheckerTexture;
 pub use texture::NoiseTexture;
 pub use texture::Texture;
@@ -67,661 +87,203 @@ fn is_ci() -> bool {
     option_env!("CI").unwrap_or_default() == "true"
 }
 
-fn ray_color(r: &Ray, background: &Color1, world: &HittableList, depth: i32) -> Vec3 {
-    let mut rec = HitRecord::new();
-    if depth <= 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
-    }
-
-    if !world.hit(r, 0.001, INFINITY, &mut rec) {
-        return *background;
-    }
-    let mut scattered = Ray::new1();
-    let mut attenuation = Color1::new(0.0, 0.0, 0.0);
-    let emitted = rec.mat_ptr.clone().unwrap().emitted(rec.u, rec.v, &rec.p);
-    if !rec
-        .mat_ptr
-        .clone()
-        .unwrap()
-        .scatter(r, &mut rec, &mut attenuation, &mut scattered)
-    {
-        return emitted;
-    }
-    emitted
-        + Vec3::elemul(
-            attenuation,
-            ray_color(&scattered, background, world, depth - 1),
-        )
-    //let mut target = rec.p.clone() + rec.normal.clone() + Vec3::random_in_hemisphere(&rec.normal);
-    //return ray_color(&Ray::new(rec.p.clone(),target - rec.p.clone()), &world, depth - 1) * 0.5;
-
-    //let unit_direction = r.dir.unit_vector();
-    //let t = 0.5 * (unit_direction.y() + 1.0);
-    //Color1::new(1.0, 1.0, 1.0) * (1.0 - t) + Color1::new(0.5, 0.7, 1.0) * t
-}
-
-fn random_scene() -> HittableList {
-    let mut world = HittableList::new();
-
-    let checker: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(CheckerTexture::new1(
-        Color1::new(0.2, 0.3, 0.1),
-        Color1::new(0.9, 0.9, 0.9),
-    )));
-    world.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Some(Arc::new(Lambertian::new1(checker))),
-    ))));
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = random_f64();
-            let center = Point3::new(
-                a as f64 + 0.9 * random_f64(),
-                0.2,
-                b as f64 + 0.9 * random_f64(),
-            );
-
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let sphere_material: Option<Arc<dyn Material + Send + Sync>>;
-
-                if choose_mat < 0.8 {
-                    let albedo = Color1::random().elemul(Color1::random());
-                    sphere_material = Some(Arc::new(Lambertian::new(&albedo)));
-                    let center2 = center + Vec3::new(0.0, random_f64_1(0.0, 0.5), 0.0);
-                    world.add(Some(Arc::new(MovingSphere::new(
-                        center,
-                        center2,
-                        0.0,
-                        1.0,
-                        0.2,
-                        sphere_material,
-                    ))));
-                } else if choose_mat < 0.95 {
-                    let albedo = Color1::random1(0.5, 1.0);
-                    let fuzz = random_f64_1(0.0, 0.5);
-                    sphere_material = Some(Arc::new(Medal::new(&albedo, fuzz)));
-                    world.add(Some(Arc::new(Sphere::new(center, 0.2, sphere_material))));
-                } else {
-                    sphere_material = Some(Arc::new(Dielectric::new(1.5)));
-                    world.add(Some(Arc::new(Sphere::new(center, 0.2, sphere_material))));
-                }
-            }
-        }
-    }
-    let material1: Option<Arc<dyn Material + Send + Sync>> = Some(Arc::new(Dielectric::new(1.5)));
-    world.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        material1,
-    ))));
-
-    let material2: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.4, 0.2, 0.1))));
-    world.add(Some(Arc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        material2,
-    ))));
-
-    let material3: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Medal::new(&Color1::new(0.7, 0.6, 0.5), 0.0)));
-    world.add(Some(Arc::new(Sphere::new(
-        Point3::new(4.0, 1.0, 0.0),
-        1.0,
-        material3,
-    ))));
-
-    let mut objects = HittableList::new();
-    objects.add(Some(Arc::new(BvhNode::new1(&mut world, 0.0, 1.0))));
-    objects
-}
-
-fn two_spheres() -> HittableList {
-    let mut objects = HittableList::new();
-    let checker: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(CheckerTexture::new1(
-        Color1::new(0.2, 0.3, 0.1),
-        Color1::new(0.9, 0.9, 0.9),
-    )));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, -10.0, 0.0),
-        10.0,
-        Some(Arc::new(Lambertian::new1(checker.clone()))),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 10.0, 0.0),
-        10.0,
-        Some(Arc::new(Lambertian::new1(checker))),
-    ))));
-    objects
-}
-
-fn two_perlin_spheres() -> HittableList {
-    let mut objects = HittableList::new();
-
-    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(4.0)));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Some(Arc::new(Lambertian::new1(pertext.clone()))),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Some(Arc::new(Lambertian::new1(pertext))),
-    ))));
-    objects
-}
-
-fn earth() -> HittableList {
-    let mut world = HittableList::new();
-    let earth_texture: Option<Arc<dyn Texture + Send + Sync>> =
-        Some(Arc::new(ImageTecture::new("earthmap.jpg")));
-    let earth_surface: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new1(earth_texture)));
-    let globe: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 0.0, 0.0),
-        2.0,
-        earth_surface,
-    )));
-    world.add(globe);
-
-    world
-}
-
-fn simple_light() -> HittableList {
-    let mut objects = HittableList::new();
-
-    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(4.0)));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Some(Arc::new(Lambertian::new1(pertext.clone()))),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Some(Arc::new(Lambertian::new1(pertext))),
-    ))));
-
-    let difflight: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(DiffuseLight::new1(Color1::new(4.0, 4.0, 4.0))));
-    objects.add(Some(Arc::new(XyRect::new(
-        3.0,
-        5.0,
-        1.0,
-        3.0,
-        -2.0,
-        difflight.clone(),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 7.0, 0.0),
-        2.0,
-        difflight.clone(),
-    ))));
-
-    objects
-}
-
-fn cornell_box() -> HittableList {
-    let mut objects = HittableList::new();
-
-    let red: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.65, 0.05, 0.05))));
-    let white: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
-    let green: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.12, 0.45, 0.15))));
-    let light: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(DiffuseLight::new1(Color1::new(15.0, 15.0, 15.0))));
-
-    objects.add(Some(Arc::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 555.0, green,
-    ))));
-    objects.add(Some(Arc::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 0.0, red,
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-    objects.add(Some(Arc::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-
-    let mut box1: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    )));
-    box1 = Some(Arc::new(RotateY::new(box1, 15.0)));
-    box1 = Some(Arc::new(Translate::new(
-        box1,
-        &Vec3::new(265.0, 0.0, 295.0),
-    )));
-    objects.add(box1);
-
-    let mut box2: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 165.0, 165.0),
-        white.clone(),
-    )));
-    box2 = Some(Arc::new(RotateY::new(box2, -18.0)));
-    box2 = Some(Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0))));
-    objects.add(box2);
-
-    objects
-}
-
-fn cornell_smoke() -> HittableList {
-    let mut objects = HittableList::new();
-
-    let red: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.65, 0.05, 0.05))));
-    let white: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
-    let green: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.12, 0.45, 0.15))));
-    let light: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(DiffuseLight::new1(Color1::new(7.0, 7.0, 7.0))));
-
-    objects.add(Some(Arc::new(YzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        green.clone(),
-    ))));
-    objects.add(Some(Arc::new(YzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        red.clone(),
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        113.0,
-        443.0,
-        127.0,
-        432.0,
-        554.0,
-        light.clone(),
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-    objects.add(Some(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    ))));
-    objects.add(Some(Arc::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-    let mut box1: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    )));
-    box1 = Some(Arc::new(RotateY::new(box1, 15.0)));
-    box1 = Some(Arc::new(Translate::new(
-        box1,
-        &Vec3::new(265.0, 0.0, 295.0),
-    )));
-
-    let mut box2: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 165.0, 165.0),
-        white.clone(),
-    )));
-    box2 = Some(Arc::new(RotateY::new(box2, -18.0)));
-    box2 = Some(Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0))));
-
-    objects.add(Some(Arc::new(ConstantMedium::new1(
-        box1,
-        0.01,
-        Color1::new(0.0, 0.0, 0.0),
-    ))));
-    objects.add(Some(Arc::new(ConstantMedium::new1(
-        box2,
-        0.01,
-        Color1::new(1.0, 1.0, 1.0),
-    ))));
-
-    objects
-}
-
-fn final_scene() -> HittableList {
-    let mut boxes1 = HittableList::new();
-    let mut objects = HittableList::new();
-    let ground: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.48, 0.83, 0.53))));
-
-    for i in 0..20 {
-        for j in 0..20 {
-            boxes1.add(Some(Arc::new(Box1::new(
-                Point3::new(-1000.0 + i as f64 * 100.0, 0.0, -1000.0 + j as f64 * 100.0),
-                Point3::new(
-                    -1000.0 + i as f64 * 100.0 + 100.0,
-                    random_f64_1(1.0, 101.0),
-                    -1000.0 + j as f64 * 100.0 + 100.0,
-                ),
-                ground.clone(),
-            ))));
-        }
-    }
-
-    objects.add(Some(Arc::new(BvhNode::new1(&mut boxes1, 0.0, 1.0))));
-
-    let light: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(DiffuseLight::new1(Color1::new(7.0, 7.0, 7.0))));
-    objects.add(Some(Arc::new(XzRect::new(
-        123.0,
-        423.0,
-        147.0,
-        412.0,
-        554.0,
-        light.clone(),
-    ))));
-
-    let center1 = Point3::new(400.0, 400.0, 200.0);
-    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
-    let moving_sphere_material: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.7, 0.3, 0.1))));
-    objects.add(Some(Arc::new(MovingSphere::new(
-        center1,
-        center2,
-        0.0,
-        1.0,
-        50.0,
-        moving_sphere_material,
-    ))));
-
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(260.0, 150.0, 45.0),
-        50.0,
-        Some(Arc::new(Dielectric::new(1.5))),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 150.0, 145.0),
-        50.0,
-        Some(Arc::new(Medal::new(&Color1::new(0.8, 0.8, 0.9), 1.0))),
-    ))));
-
-    let mut boundary: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Sphere::new(
-        Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        Some(Arc::new(Dielectric::new(1.5))),
-    )));
-    objects.add(boundary.clone());
-    objects.add(Some(Arc::new(ConstantMedium::new1(
-        boundary,
-        0.2,
-        Color1::new(0.2, 0.4, 0.9),
-    ))));
-    boundary = Some(Arc::new(Sphere::new(
-        Point3::new(0.0, 0.0, 0.0),
-        5000.0,
-        Some(Arc::new(Dielectric::new(1.5))),
-    )));
-    objects.add(Some(Arc::new(ConstantMedium::new1(
-        boundary,
-        0.0001,
-        Color1::new(1.0, 1.0, 1.0),
-    ))));
-
-    let emat: Option<Arc<dyn Material + Send + Sync>> = Some(Arc::new(Lambertian::new1(Some(
-        Arc::new(ImageTecture::new("earthmap.jpg")),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(400.0, 200.0, 400.0),
-        100.0,
-        emat,
-    ))));
-    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(0.1)));
-    objects.add(Some(Arc::new(Sphere::new(
-        Point3::new(220.0, 280.0, 300.0),
-        80.0,
-        Some(Arc::new(Lambertian::new1(pertext))),
-    ))));
-
-    let mut boxes2 = HittableList::new();
-    let white: Option<Arc<dyn Material + Send + Sync>> =
-        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
-    let ns = 1000;
-    for _j in 0..ns {
-        boxes2.add(Some(Arc::new(Sphere::new(
-            Point3::random1(0.0, 165.0),
-            10.0,
-            white.clone(),
-        ))));
+fn main() {
+    let args = Args::parse();
+    if args.list_scenes {
+        Args::print_scenes();
+        return;
     }
 
-    objects.add(Some(Arc::new(Translate::new(
-        Some(Arc::new(RotateY::new(
-            Some(Arc::new(BvhNode::new1(&mut boxes2, 0.0, 1.0))),
-            15.0,
-        ))),
-        &Vec3::new(-100.0, 270.0, 395.0),
-    ))));
-
-    objects
-}
-
-fn main() {
     // get environment variable CI, which is true for GitHub Actions
     let is_ci = is_ci();
 
     println!("CI: {}", is_ci);
 
-    // Image
-    let mut aspect_ratio = 16.0 / 9.0;
-    let mut width: usize = 400;
-    let path = "output/test.jpg";
-    let quality = 100; // From 0 to 100, suggested value: 60
-    let mut samples_per_pixel = 100;
-    let max_depth = 50;
-
-    // World
-    let world;
-    let lookfrom;
-    let lookat;
-    let vfov;
-    let mut aperture = 0.0;
-    let mut background = Vec3::new(0.0, 0.0, 0.0);
-
-    match 0 {
-        1 => {
-            world = Arc::new(random_scene());
-            background = Color1::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-
-        2 => {
-            world = Arc::new(two_spheres());
-            background = Color1::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-
-        3 => {
-            world = Arc::new(two_perlin_spheres());
-            background = Color1::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
+    // Without a seed pick one, so that the render can be repeated.
+    let seed = args.seed.unwrap_or_else(rand::random);
+    seed_rng(seed);
 
-        4 => {
-            world = Arc::new(earth());
-            background = Color1::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
+    let thread_number = args.threads.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
 
-        5 => {
-            world = Arc::new(simple_light());
-            samples_per_pixel = 400;
-            background = Color1::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(26.0, 3.0, 6.0);
-            lookat = Point3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
-        }
+    // World
+    let bvh_options = BvhOptions {
+        method: args.bvh,
+        layout: args.bvh_layout,
+        threads: thread_number,
+    };
+    let (scene, scene_name) = match &args.scene_file {
+        Some(path) => match scene_file::load_scene(Path::new(path), bvh_options) {
+            Ok(scene) => (scene, path.as_str()),
+            Err(e) => {
+                eprintln!("error: failed to load scene file {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => (
+            scene::build_scene(&args.scene, bvh_options).unwrap(),
+            args.scene.as_str(),
+        ),
+    };
 
-        6 => {
-            world = Arc::new(cornell_box());
-            aspect_ratio = 1.0;
-            width = 600;
-            samples_per_pixel = 200;
-            background = Color1::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+    // Image
+    let width = args.width.unwrap_or(scene.width);
+    let (height, aspect_ratio) = match args.height {
+        Some(height) => (height, width as f64 / height as f64),
+        None => {
+            let aspect_ratio = args.aspect_ratio.unwrap_or(scene.aspect_ratio);
+            ((width as f64 / aspect_ratio) as usize, aspect_ratio)
         }
-
-        7 => {
-            world = Arc::new(cornell_smoke());
-            aspect_ratio = 1.0;
-            width = 600;
-            samples_per_pixel = 200;
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+    };
+    if width < 2 || height < 2 {
+        eprintln!(
+            "error: a {}x{} image is too small, images need at least 2 rows and columns",
+            width, height
+        );
+        std::process::exit(1);
+    }
+    let path = args.output.as_str();
+    let format = match args
+        .format
+        .or_else(|| OutputFormat::from_path(Path::new(path)))
+    {
+        Some(format) => format,
+        None => {
+            eprintln!(
+                "error: cannot tell the image format of {}, use a .jpg, .png, .ppm, .hdr or .exr \
+                 extension or pass --format",
+                path
+            );
+            std::process::exit(1);
         }
+    };
+    let quality = args.quality; // From 0 to 100, suggested value: 60
+    let samples_per_pixel = args.spp.unwrap_or(scene.samples_per_pixel);
+    let tracer = PathTracer {
+        world: Arc::new(scene.world),
+        lights: Arc::new(scene.lights),
+        background: scene.background,
+        rr_depth: args.rr_depth,
+        max_depth: args.max_depth,
+    };
 
-        _ => {
-            world = Arc::new(final_scene());
-            aspect_ratio = 1.0;
-            width = 800;
-            samples_per_pixel = 50;
-            background = Color1::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(478.0, 278.0, -600.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+    // Create image data
+    let img = Mutex::new(Film::new(width, height));
+    let display = DisplayTransform {
+        tone_map: args.tone_map,
+        exposure: args.exposure,
+        white_point: args.white_point,
+    };
+    let write_image = |film: &Film| {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                ImageError::IoError(io::Error::new(
+                    e.kind(),
+                    format!("cannot create directory \"{}\": {}", parent.display(), e),
+                ))
+            })?;
         }
-    }
+        save_image(Path::new(path), format, quality, film, &display)
+    };
 
-    let height = (width as f64 / aspect_ratio) as usize;
-    // Create image data
-    let img = Arc::new(Mutex::new(ImageBuffer::new(
-        width.try_into().unwrap(),
-        height.try_into().unwrap(),
-    )));
+    // Progressive renders add the samples in passes and write the image in between.
+    // Adaptive renders start with `min_spp` samples everywhere, then keep adding passes to
+    // the pixels that are still noisy until they reach `samples_per_pixel`.
+    let samples_per_pixel = samples_per_pixel.max(1) as u32;
+    let min_spp = args.min_spp.clamp(1, samples_per_pixel);
+    let pass_spp = args
+        .progressive
+        .unwrap_or(if args.adaptive.is_some() {
+            min_spp
+        } else {
+            samples_per_pixel
+        })
+        .clamp(1, samples_per_pixel);
+    let first_pass_spp = if args.adaptive.is_some() {
+        min_spp
+    } else {
+        pass_spp
+    };
 
     // Progress bar UI powered by library `indicatif`
     // You can use indicatif::ProgressStyle to make it more beautiful)
     // You can also use indicatif::MultiProgress in multi-threading to show progress of each thread
     let bar = if is_ci {
-        Arc::new(ProgressBar::hidden())
+        ProgressBar::hidden()
     } else {
-        Arc::new(ProgressBar::new((height * width) as u64))
+        ProgressBar::new((height * width) as u64 * samples_per_pixel as u64)
     };
 
-    // Camera
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-
-    let cam = Camera::new(
-        &lookfrom,
-        &lookat,
-        &vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        (dist_to_focus, 0.0, 1.0),
-    );
-
+    println!("Scene: {}", scene_name);
     println!("Image size: {}x{}", width, height);
-    println!("JPEG quality: {}", quality);
+    if format == OutputFormat::Jpeg {
+        println!("JPEG quality: {}", quality);
+    }
     println!("Samples per pixel: {}", samples_per_pixel);
-    println!("Reflection max depth: {}", max_depth);
+    println!("Seed: {}", seed);
+    println!("Sampler: {:?}", args.sampler);
+    if let Some(threshold) = args.adaptive {
+        println!(
+            "Adaptive: at least {} samples, until the relative error is below {}",
+            min_spp, threshold
+        );
+    }
+    if args.progressive.is_some() {
+        println!("Progressive: passes of {} samples", pass_spp);
+    }
+    for stats in &scene.bvh_stats {
+        println!("BVH: {}", stats);
+    }
+    println!("Russian roulette after {} bounces", tracer.rr_depth);
+    if let Some(max_depth) = tracer.max_depth {
+        println!("Reflection max depth: {}", max_depth);
+    }
 
-    let mut handles = vec![];
-    let thread_number = 15;
+    let renderer = Renderer {
+        tracer,
+        // Camera
+        camera: scene.camera.build(aspect_ratio),
+        width,
+        height,
+        tiles: make_tiles(width, height, args.tile_size, args.tile_order),
+        threads: thread_number,
+        sampler: args.sampler.build(samples_per_pixel, seed),
+        seed,
+    };
 
-    println!("Rendering with {} Threads", thread_number);
+    println!(
+        "Rendering {} tiles of {}x{} ({:?} order) with {} Threads",
+        renderer.tiles.len(),
+        args.tile_size,
+        args.tile_size,
+        args.tile_order,
+        thread_number
+    );
+    let plan = RenderPlan {
+        samples_per_pixel,
+        first_pass_spp,
+        pass_spp,
+        adaptive: args.adaptive,
+        snapshots: args.progressive.is_some(),
+        snapshot_interval: args.snapshot_interval,
+    };
     let start = Instant::now();
-    let timers = Arc::new(Mutex::new(vec![Duration::default(); thread_number]));
-
-    for t in 0..thread_number {
-        let world = Arc::clone(&world);
-        let img = Arc::clone(&img);
-        let bar = Arc::clone(&bar);
-        let timers_clone = Arc::clone(&timers);
-        let handle = thread::spawn(move || {
-            let start_time = Instant::now();
-            for j in (t * height / thread_number)..((t + 1) * height / thread_number) {
-                for i in 0..width {
-                    let mut pixel_color = Color1::new(0.0, 0.0, 0.0);
-                    for _s in 0..samples_per_pixel {
-                        let u = (i as f64 + random_f64()) / (width - 1) as f64;
-                        let v = (j as f64 + random_f64()) / (height - 1) as f64;
-                        let r = cam.get_ray(u, v);
-                        pixel_color += ray_color(&r, &background, &world, max_depth);
-                    }
-                    write_color(
-                        &pixel_color,
-                        &mut img.lock().unwrap(),
-                        i,
-                        height - j - 1,
-                        samples_per_pixel,
-                    );
-                    bar.inc(1);
-                }
-            }
-            let mut timer = timers_clone.lock().unwrap();
-            *timer.get_mut(t).unwrap() = start_time.elapsed();
-        });
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    let timers = renderer.render(&img, &plan, &bar, |film, samples_done| {
+        match write_image(film) {
+            Ok(()) => bar.println(format!(
+                "Snapshot at {} samples per pixel written to \"{}\"",
+                samples_done, path
+            )),
+            Err(e) => bar.println(format!("Writing snapshot fails: {}", e)),
+        }
+    });
 
     // Finish progress bar
     bar.finish();
 
-    let timers = timers.lock().unwrap();
     for (i, timer) in timers.iter().enumerate() {
         println!("thread {} 运行时间 : {:?}", i, timer);
     }
@@ -729,13 +291,26 @@ fn main() {
     let total_duration = start.elapsed();
     println!("总体运行时间: {:?}", total_duration);
 
+    let film = img.into_inner().unwrap();
+    let total_samples: u64 = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| film.samples(x, y) as u64)
+        .sum();
+    println!(
+        "Average samples per pixel: {:.1}",
+        total_samples as f64 / (width * height) as f64
+    );
+
     // Output image to file
     println!("Output image as \"{}\"\n Author: {}", path, AUTHOR);
-    let output_image =
-        image::DynamicImage::ImageRgb8(Mutex::into_inner(Arc::into_inner(img).unwrap()).unwrap());
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("Outputting image fails."),
+    if let Err(e) = write_image(&film) {
+        eprintln!("error: failed to write image {}: {}", path, e);
+        std::process::exit(1);
+    }
+    if let Some(heatmap) = &args.heatmap {
+        if let Err(e) = save_heatmap(Path::new(heatmap), &film) {
+            eprintln!("error: failed to write heatmap {}: {}", heatmap, e);
+            std::process::exit(1);
+        }
     }
 }
@@ -1,23 +1,70 @@
 use crate::hittable::HitRecord;
+use crate::microfacet::{
+    fresnel_conductor_rgb, reflect, PrincipledBsdf, RoughDielectricBsdf, TrowbridgeReitz,
+};
+use crate::onb::Onb;
+use crate::pdf::{
+    CosinePdf, MicrofacetReflectionPdf, MicrofacetTransmissionPdf, Pdf, PrincipledPdf, SpherePdf,
+};
+use crate::sampler::next_1d;
 use crate::vec3::Vec3;
 use crate::{random_f64, ray, texture, vec3, Point3};
 pub use ray::Ray;
+use serde::Deserialize;
 
+use std::f64::consts::PI;
 use std::sync::Arc;
 pub use texture::SolidColor;
 pub use texture::Texture;
 use vec3::Color1;
 
+/// How a material scatters one incoming ray. Specular materials (smooth `Dielectric`s and
+/// `Conductor`s) pick the outgoing ray themselves and are skipped by light sampling; all
+/// others describe the directions they scatter into with `pdf_ptr`.
+pub struct ScatterRecord {
+    pub specular_ray: Ray,
+    pub is_specular: bool,
+    pub attenuation: Color1,
+    pub pdf_ptr: Option<Arc<dyn Pdf + Send + Sync>>,
+    /// The part each `MixMaterial` that scattered picked, which its `eval` goes to, keyed
+    /// by the address of the mix. A path through nested materials meets each mix once.
+    pub picks: Vec<(usize, Arc<dyn Material + Send + Sync>)>,
+}
+
+impl Default for ScatterRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScatterRecord {
+    pub fn new() -> Self {
+        Self {
+            specular_ray: Ray::new1(),
+            is_specular: false,
+            attenuation: Color1::new(0.0, 0.0, 0.0),
+            pdf_ptr: None,
+            picks: Vec::new(),
+        }
+    }
+}
+
 pub trait Material {
     fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color1;
 
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &mut HitRecord,
-        attenuation: &mut Color1,
-        scattered: &mut Ray,
-    ) -> bool;
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool;
+
+    /// Solid-angle density of scattering `r_in` into `scattered`, for non-specular materials.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// The BSDF times the cosine to the normal, for scattering `r_in` into `scattered` at a
+    /// non-specular hit. Materials that scatter their `attenuation` with density
+    /// `scattering_pdf` keep this default.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, srec: &ScatterRecord, scattered: &Ray) -> Color1 {
+        srec.attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
 }
 
 pub struct Lambertian {
@@ -40,72 +87,160 @@ impl Material for Lambertian {
         Color1::new(0.0, 0.0, 0.0)
     }
 
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &mut HitRecord,
-        attenuation: &mut Color1,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
-        *scattered = Ray::new(rec.p, scatter_direction, r_in.time());
-        *attenuation = self.albedo.clone().unwrap().value(rec.u, rec.v, &rec.p);
+    fn scatter(&self, _r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.albedo.clone().unwrap().value_at(rec);
+        srec.pdf_ptr = Some(Arc::new(CosinePdf::new(&rec.normal)));
         true
     }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = rec.normal * scattered.direction().unit_vector();
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
 }
 
-pub struct Medal {
-    albedo: Color1,
-    fuzz: f64,
+/// Measured complex indices of refraction `eta + i k` of metals, at red, green and blue
+/// wavelengths.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalPreset {
+    Gold,
+    Copper,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+    Silver,
 }
 
-impl Medal {
-    pub fn new(a: &Color1, f: f64) -> Self {
-        let mut b = 1.0;
-        if f < 1.0 {
-            b = f;
+impl MetalPreset {
+    pub const ALL: [MetalPreset; 4] = [
+        MetalPreset::Gold,
+        MetalPreset::Copper,
+        MetalPreset::Aluminium,
+        MetalPreset::Silver,
+    ];
+
+    /// `(eta, k)`
+    pub fn ior(&self) -> (Color1, Color1) {
+        match self {
+            MetalPreset::Gold => (
+                Color1::new(0.143, 0.374, 1.442),
+                Color1::new(3.983, 2.385, 1.603),
+            ),
+            MetalPreset::Copper => (
+                Color1::new(0.200, 0.924, 1.102),
+                Color1::new(3.912, 2.452, 2.142),
+            ),
+            MetalPreset::Aluminium => (
+                Color1::new(1.657, 0.880, 0.521),
+                Color1::new(9.224, 6.270, 4.837),
+            ),
+            MetalPreset::Silver => (
+                Color1::new(0.155, 0.117, 0.138),
+                Color1::new(4.828, 3.122, 2.147),
+            ),
         }
+    }
+}
+
+/// A metal: GGX microfacets with Smith masking and the Fresnel reflectance of a complex
+/// index of refraction. Rough surfaces sample the visible normals; near-zero roughness
+/// reflects like a mirror.
+pub struct Conductor {
+    eta: Color1,
+    k: Color1,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color1, k: Color1, roughness: f64) -> Self {
         Self {
-            albedo: *a,
-            fuzz: b,
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
         }
     }
+
+    pub fn from_preset(preset: MetalPreset, roughness: f64) -> Self {
+        let (eta, k) = preset.ior();
+        Self::new(eta, k, roughness)
+    }
+
+    /// A metal reflecting `albedo` head on: `eta` = 1 and the `k` that gives that reflectance.
+    pub fn from_reflectance(albedo: &Color1, roughness: f64) -> Self {
+        let k = |r: f64| {
+            let r = r.clamp(0.0, 0.9999);
+            2.0 * (r / (1.0 - r)).sqrt()
+        };
+        Self::new(
+            Color1::new(1.0, 1.0, 1.0),
+            Color1::new(k(albedo.x), k(albedo.y), k(albedo.z)),
+            roughness,
+        )
+    }
 }
 
-impl Material for Medal {
+impl Material for Conductor {
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color1 {
         Color1::new(0.0, 0.0, 0.0)
     }
 
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &mut HitRecord,
-        attenuation: &mut Color1,
-        scattered: &mut Ray,
-    ) -> bool {
-        let reflected = Vec3::reflect(&r_in.direction().unit_vector().clone(), &rec.normal.clone());
-        *scattered = Ray::new(
-            rec.p,
-            reflected + Vec3::random_in_unit_sphere() * self.fuzz,
-            r_in.time(),
-        );
-        *attenuation = self.albedo;
-        (scattered.direction() * rec.normal) > 0.0
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        let wo = -r_in.direction().unit_vector();
+        if self.distribution.effectively_smooth() {
+            let cos_theta = (wo * rec.normal).abs();
+            srec.specular_ray = Ray::new(rec.p, reflect(&wo, &rec.normal), r_in.time());
+            srec.is_specular = true;
+            srec.attenuation = fresnel_conductor_rgb(cos_theta, &self.eta, &self.k);
+            srec.pdf_ptr = None;
+            return true;
+        }
+        srec.is_specular = false;
+        srec.attenuation = Color1::new(1.0, 1.0, 1.0);
+        srec.pdf_ptr = Some(Arc::new(MicrofacetReflectionPdf::new(
+            &rec.normal,
+            &wo,
+            self.distribution,
+        )));
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color1 {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.project(&-r_in.direction().unit_vector());
+        let wi = uvw.project(&scattered.direction().unit_vector());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color1::new(0.0, 0.0, 0.0);
+        }
+        let wm = (wo + wi).unit_vector();
+        let fresnel = fresnel_conductor_rgb(wo * wm, &self.eta, &self.k);
+        fresnel * (self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4.0 * wo.z))
     }
 }
 
+/// Glass and other clear materials. Smooth ones reflect or refract specularly; rough ones
+/// scatter through GGX microfacets. Light travelling inside is absorbed by `absorption` per
+/// unit distance (Beer-Lambert), applied when the ray reaches the boundary from inside.
 pub struct Dielectric {
     ir: f64,
+    distribution: TrowbridgeReitz,
+    absorption: Color1,
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f64) -> Self {
+        Self::new1(index_of_refraction, 0.0, Color1::new(0.0, 0.0, 0.0))
+    }
+
+    pub fn new1(index_of_refraction: f64, roughness: f64, absorption: Color1) -> Self {
         Self {
             ir: index_of_refraction,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            absorption,
         }
     }
 
@@ -114,6 +249,31 @@ impl Dielectric {
         r0 = r0 * r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
+
+    /// Fraction of the light that is left after travelling from the origin of `r_in` to the
+    /// hit, which is inside the material when the hit is on a back face.
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color1 {
+        if rec.front_face {
+            return Color1::new(1.0, 1.0, 1.0);
+        }
+        let distance = rec.t * r_in.direction().length();
+        Color1::new(
+            (-self.absorption.x * distance).exp(),
+            (-self.absorption.y * distance).exp(),
+            (-self.absorption.z * distance).exp(),
+        )
+    }
+
+    fn rough_bsdf(&self, rec: &HitRecord) -> RoughDielectricBsdf {
+        RoughDielectricBsdf {
+            distribution: self.distribution,
+            eta: if rec.front_face {
+                self.ir
+            } else {
+                1.0 / self.ir
+            },
+        }
+    }
 }
 
 impl Material for Dielectric {
@@ -121,14 +281,21 @@ impl Material for Dielectric {
         Color1::new(0.0, 0.0, 0.0)
     }
 
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &mut HitRecord,
-        attenuation: &mut Color1,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color1::new(1.0, 1.0, 1.0);
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        if !self.distribution.effectively_smooth() {
+            srec.is_specular = false;
+            srec.attenuation = Color1::new(1.0, 1.0, 1.0);
+            srec.pdf_ptr = Some(Arc::new(MicrofacetTransmissionPdf::new(
+                &rec.normal,
+                &-r_in.direction(),
+                self.rough_bsdf(rec),
+            )));
+            return true;
+        }
+
+        srec.is_specular = true;
+        srec.pdf_ptr = None;
+        srec.attenuation = self.transmittance(r_in, rec);
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
         } else {
@@ -152,19 +319,330 @@ impl Material for Dielectric {
             Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
         };
 
-        *scattered = Ray::new(rec.p, direction, r_in.time());
+        srec.specular_ray = Ray::new(rec.p, direction, r_in.time());
         true
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color1 {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.project(&-r_in.direction().unit_vector());
+        let wi = uvw.project(&scattered.direction().unit_vector());
+        self.transmittance(r_in, rec) * (self.rough_bsdf(rec).f(&wo, &wi) * wi.z.abs())
+    }
 }
 
+/// The Disney "principled" material: one set of artist-friendly parameters, each driven by
+/// a texture, covering plastics, metals, glass, cloth and lacquered surfaces. Scalar
+/// parameters read the mean of their texture's channels; `ior` is the index of refraction
+/// of the inside. The surface is never perfectly smooth, see `PrincipledBsdf`.
+pub struct Principled {
+    pub base_color: Option<Arc<dyn Texture + Send + Sync>>,
+    pub metallic: Option<Arc<dyn Texture + Send + Sync>>,
+    pub roughness: Option<Arc<dyn Texture + Send + Sync>>,
+    pub specular: Option<Arc<dyn Texture + Send + Sync>>,
+    pub specular_tint: Option<Arc<dyn Texture + Send + Sync>>,
+    pub sheen: Option<Arc<dyn Texture + Send + Sync>>,
+    pub sheen_tint: Option<Arc<dyn Texture + Send + Sync>>,
+    pub clearcoat: Option<Arc<dyn Texture + Send + Sync>>,
+    pub clearcoat_gloss: Option<Arc<dyn Texture + Send + Sync>>,
+    pub transmission: Option<Arc<dyn Texture + Send + Sync>>,
+    pub ior: Option<Arc<dyn Texture + Send + Sync>>,
+}
+
+impl Principled {
+    /// A dielectric of `base_color` with the defaults of the Disney BRDF; set the other
+    /// fields to change them.
+    pub fn new(base_color: Option<Arc<dyn Texture + Send + Sync>>) -> Self {
+        let scalar = |value: f64| -> Option<Arc<dyn Texture + Send + Sync>> {
+            Some(Arc::new(SolidColor::scalar(value)))
+        };
+        Self {
+            base_color,
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            specular: scalar(0.5),
+            specular_tint: scalar(0.0),
+            sheen: scalar(0.0),
+            sheen_tint: scalar(0.5),
+            clearcoat: scalar(0.0),
+            clearcoat_gloss: scalar(1.0),
+            transmission: scalar(0.0),
+            ior: scalar(1.5),
+        }
+    }
+
+    /// The parameters looked up at `rec`.
+    fn bsdf(&self, rec: &HitRecord) -> PrincipledBsdf {
+        let scalar = |texture: &Option<Arc<dyn Texture + Send + Sync>>| {
+            texture.as_ref().unwrap().scalar_at(rec)
+        };
+        let ior = scalar(&self.ior);
+        PrincipledBsdf {
+            base_color: self.base_color.as_ref().unwrap().value_at(rec),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            eta: if rec.front_face { ior } else { 1.0 / ior },
+        }
+    }
+}
+
+impl Material for Principled {
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color1 {
+        Color1::new(0.0, 0.0, 0.0)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = Color1::new(1.0, 1.0, 1.0);
+        srec.pdf_ptr = Some(Arc::new(PrincipledPdf::new(
+            &rec.normal,
+            &-r_in.direction(),
+            self.bsdf(rec),
+        )));
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color1 {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.project(&-r_in.direction().unit_vector());
+        let wi = uvw.project(&scattered.direction().unit_vector());
+        self.bsdf(rec).f(&wo, &wi) * wi.z.abs()
+    }
+}
+
+/// Two materials blended by a `mask`: where its mean is 0 the surface is `first`, where it
+/// is 1 it is `second`. Each hit scatters off one of the two, picked with the probability of
+/// its weight; emission is blended.
+///
+/// The pick draws from the sampler, so renders stay reproducible, and is kept in the
+/// `ScatterRecord` for `eval`.
+pub struct MixMaterial {
+    first: Option<Arc<dyn Material + Send + Sync>>,
+    second: Option<Arc<dyn Material + Send + Sync>>,
+    mask: Option<Arc<dyn Texture + Send + Sync>>,
+}
+
+impl MixMaterial {
+    pub fn new(
+        first: Option<Arc<dyn Material + Send + Sync>>,
+        second: Option<Arc<dyn Material + Send + Sync>>,
+        mask: Option<Arc<dyn Texture + Send + Sync>>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            mask,
+        }
+    }
+
+    /// Tells this mix apart from others in `ScatterRecord::picks`.
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl Material for MixMaterial {
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color1 {
+        let c = self.mask.as_ref().unwrap().value(u, v, p);
+        let weight = ((c.x + c.y + c.z) / 3.0).clamp(0.0, 1.0);
+        self.first.as_ref().unwrap().emitted(u, v, p) * (1.0 - weight)
+            + self.second.as_ref().unwrap().emitted(u, v, p) * weight
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        let weight = self.mask.as_ref().unwrap().scalar_at(rec);
+        let part = if next_1d() < weight {
+            self.second.clone().unwrap()
+        } else {
+            self.first.clone().unwrap()
+        };
+        srec.picks.push((self.id(), part.clone()));
+        part.scatter(r_in, rec, srec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, srec: &ScatterRecord, scattered: &Ray) -> Color1 {
+        match srec.picks.iter().find(|(id, _)| *id == self.id()) {
+            Some((_, part)) => part.eval(r_in, rec, srec, scattered),
+            None => Color1::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/// The normal of `rec` pointing out of the surface, whichever side the ray hit.
+fn outward_normal(rec: &HitRecord) -> Vec3 {
+    if rec.front_face {
+        rec.normal
+    } else {
+        -rec.normal
+    }
+}
+
+/// `rec` with the outward normal `normal` in place of its own, still facing the ray.
+fn with_normal(rec: &HitRecord, normal: &Vec3) -> HitRecord {
+    let mut shaded = rec.clone();
+    shaded.normal = if rec.front_face { *normal } else { -*normal };
+    shaded
+}
+
+/// Whether `scattered` leaves on the same side of the surface for the shading normal of
+/// `shaded` as for the true normal of `rec`; light that does not would leak through.
+fn same_side(rec: &HitRecord, shaded: &HitRecord, scattered: &Ray) -> bool {
+    let direction = scattered.direction();
+    (direction * rec.normal) * (direction * shaded.normal) > 0.0
+}
+
+/// Declares the `Material` impl of a wrapper that shades `material` with the normal given
+/// by its `shade` method. The hit record the integrator holds keeps the true normal.
+macro_rules! shading_normal_material {
+    ($name:ident) => {
+        impl Material for $name {
+            fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color1 {
+                self.material.as_ref().unwrap().emitted(u, v, p)
+            }
+
+            fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+                let mut shaded = self.shade(rec);
+                self.material
+                    .as_ref()
+                    .unwrap()
+                    .scatter(r_in, &mut shaded, srec)
+            }
+
+            fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+                let shaded = self.shade(rec);
+                self.material
+                    .as_ref()
+                    .unwrap()
+                    .scattering_pdf(r_in, &shaded, scattered)
+            }
+
+            fn eval(
+                &self,
+                r_in: &Ray,
+                rec: &HitRecord,
+                srec: &ScatterRecord,
+                scattered: &Ray,
+            ) -> Color1 {
+                let shaded = self.shade(rec);
+                if !same_side(rec, &shaded, scattered) {
+                    return Color1::new(0.0, 0.0, 0.0);
+                }
+                self.material
+                    .as_ref()
+                    .unwrap()
+                    .eval(r_in, &shaded, srec, scattered)
+            }
+        }
+    };
+}
+
+/// `material` with its normal taken from a tangent-space normal map: red, green and blue
+/// in `[0, 1]` are the components along `dpdu`, `dpdv` and the normal, so the flat color is
+/// `(0.5, 0.5, 1)`.
+pub struct NormalMap {
+    material: Option<Arc<dyn Material + Send + Sync>>,
+    map: Option<Arc<dyn Texture + Send + Sync>>,
+}
+
+impl NormalMap {
+    pub fn new(
+        material: Option<Arc<dyn Material + Send + Sync>>,
+        map: Option<Arc<dyn Texture + Send + Sync>>,
+    ) -> Self {
+        Self { material, map }
+    }
+
+    fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let n = outward_normal(rec);
+        let tangent = rec.dpdu - n * (rec.dpdu * n);
+        if tangent.length_squared() == 0.0 {
+            return rec.clone();
+        }
+        let tangent = tangent.unit_vector();
+        let bitangent = Vec3::cross(&n, &tangent);
+        // Follow `dpdv`, which mirrored texture coordinates turn around.
+        let bitangent = if bitangent * rec.dpdv < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+        let c = self.map.as_ref().unwrap().value_at(rec) * 2.0 - 1.0;
+        let normal = tangent * c.x + bitangent * c.y + n * c.z;
+        if normal.length_squared() == 0.0 {
+            return rec.clone();
+        }
+        with_normal(rec, &normal.unit_vector())
+    }
+}
+
+shading_normal_material!(NormalMap);
+
+/// Step in `u` and `v` for the finite differences of bump maps.
+const BUMP_DELTA: f64 = 5e-4;
+
+/// `material` on a surface displaced along its normal by `scale` times the mean of
+/// `height`, without moving it: only the normal is bent. Solid textures like `NoiseTexture`
+/// work as well as image ones.
+pub struct BumpMap {
+    material: Option<Arc<dyn Material + Send + Sync>>,
+    height: Option<Arc<dyn Texture + Send + Sync>>,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(
+        material: Option<Arc<dyn Material + Send + Sync>>,
+        height: Option<Arc<dyn Texture + Send + Sync>>,
+        scale: f64,
+    ) -> Self {
+        Self {
+            material,
+            height,
+            scale,
+        }
+    }
+
+    fn height(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let c = self.height.as_ref().unwrap().value(u, v, p);
+        self.scale * (c.x + c.y + c.z) / 3.0
+    }
+
+    fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let n = outward_normal(rec);
+        let h = self.height(rec.u, rec.v, &rec.p);
+        let p_u = rec.p + rec.dpdu * BUMP_DELTA;
+        let p_v = rec.p + rec.dpdv * BUMP_DELTA;
+        let dhdu = (self.height(rec.u + BUMP_DELTA, rec.v, &p_u) - h) / BUMP_DELTA;
+        let dhdv = (self.height(rec.u, rec.v + BUMP_DELTA, &p_v) - h) / BUMP_DELTA;
+        // The derivatives of the displaced point p + h n, dropping those of n itself.
+        let dpdu = rec.dpdu + n * dhdu;
+        let dpdv = rec.dpdv + n * dhdv;
+        let normal = Vec3::cross(&dpdu, &dpdv);
+        if normal.length_squared() == 0.0 {
+            return rec.clone();
+        }
+        let normal = normal.unit_vector();
+        with_normal(rec, &if normal * n < 0.0 { -normal } else { normal })
+    }
+}
+
+shading_normal_material!(BumpMap);
+
 pub struct DiffuseLight {
     emit: Option<Arc<dyn Texture + Send + Sync>>,
 }
 
 impl DiffuseLight {
-    /*pub fn new(a: Option<Arc<dyn Texture + Send + Sync>>) -> Self {
+    pub fn new(a: Option<Arc<dyn Texture + Send + Sync>>) -> Self {
         Self { emit: a }
-    }*/
+    }
 
     pub fn new1(c: Color1) -> Self {
         Self {
@@ -174,13 +652,7 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(
-        &self,
-        _r_in: &Ray,
-        _rec: &mut HitRecord,
-        _attenuation: &mut Color1,
-        _scattered: &mut Ray,
-    ) -> bool {
+    fn scatter(&self, _r_in: &Ray, _rec: &mut HitRecord, _srec: &mut ScatterRecord) -> bool {
         false
     }
 
@@ -200,27 +672,230 @@ impl Isotropic {
         }
     }
 
-    /*    pub fn new1(a:Option<Arc<dyn Texture + Send + Sync>>) -> Self {
-        Self{
-            albedo:a,
-        }
-    }*/
+    pub fn new1(a: Option<Arc<dyn Texture + Send + Sync>>) -> Self {
+        Self { albedo: a }
+    }
 }
 
 impl Material for Isotropic {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &mut HitRecord,
-        attenuation: &mut Color1,
-        scattered: &mut Ray,
-    ) -> bool {
-        *scattered = Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.time());
-        *attenuation = self.albedo.clone().unwrap().value(rec.u, rec.v, &rec.p);
+    fn scatter(&self, _r_in: &Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.albedo.clone().unwrap().value_at(rec);
+        srec.pdf_ptr = Some(Arc::new(SpherePdf));
         true
     }
 
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color1 {
         Color1::new(0.0, 0.0, 0.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directional albedo of `material` lit from `wo` above a surface facing +z, estimated
+    /// by importance sampling and by uniform sampling of the sphere.
+    fn albedo(material: &dyn Material, wo: &Vec3, front_face: bool) -> (f64, f64) {
+        let r_in = Ray::new(*wo, -*wo, 0.0);
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = front_face;
+        rec.t = 1.0;
+        let mut srec = ScatterRecord::new();
+        assert!(material.scatter(&r_in, &mut rec, &mut srec));
+        let pdf = srec.pdf_ptr.clone().unwrap();
+        let f = |direction: &Vec3| {
+            material
+                .eval(&r_in, &rec, &srec, &Ray::new(rec.p, *direction, 0.0))
+                .x
+        };
+        let n = 400_000;
+        let sampled = (0..n)
+            .map(|_| {
+                let direction = pdf.generate();
+                let p = pdf.value(&direction);
+                if p > 0.0 {
+                    f(&direction) / p
+                } else {
+                    0.0
+                }
+            })
+            .sum::<f64>()
+            / n as f64;
+        let uniform =
+            (0..n).map(|_| f(&Vec3::random_unit_vector())).sum::<f64>() * 4.0 * PI / n as f64;
+        (sampled, uniform)
+    }
+
+    #[test]
+    fn test_rough_dielectric() {
+        // Entering and leaving glass, light is reflected or transmitted, less what masking
+        // loses; the sampled density must match `value`.
+        for (wo, front_face) in [
+            (Vec3::new(0.0, 0.0, 1.0), true),
+            (Vec3::new(0.6, 0.0, 0.8), true),
+            (Vec3::new(0.3, 0.0, 0.95), false),
+        ] {
+            let glass = Dielectric::new1(1.5, 0.6, Color1::new(0.0, 0.0, 0.0));
+            let (sampled, uniform) = albedo(&glass, &wo.unit_vector(), front_face);
+            assert!(sampled > 0.8 && sampled <= 1.01, "{}", sampled);
+            assert!(
+                (sampled - uniform).abs() < 0.05,
+                "{} vs {}",
+                sampled,
+                uniform
+            );
+        }
+
+        // Light crossing 2 units of a medium absorbing 0.5 per unit in red.
+        let tinted = Dielectric::new1(1.5, 0.0, Color1::new(0.5, 0.0, 0.0));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, -1.0);
+        rec.t = 1.0;
+        let mut srec = ScatterRecord::new();
+        assert!(tinted.scatter(&r_in, &mut rec, &mut srec));
+        assert!((srec.attenuation.x - (-1.0f64).exp()).abs() < 1e-12);
+        assert_eq!(srec.attenuation.y, 1.0);
+    }
+
+    #[test]
+    fn test_mix_material() {
+        // White and black diffuse mixed 1 : 3 reflect a quarter of the light on average, and
+        // a light mixed the same way glows at a quarter of its radiance.
+        let white: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(Lambertian::new(&Color1::new(1.0, 1.0, 1.0))));
+        let black: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(Lambertian::new(&Color1::new(0.0, 0.0, 0.0))));
+        let mask: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(SolidColor::scalar(0.25)));
+        let mixed = MixMaterial::new(black, white.clone(), mask.clone());
+        let n = 100_000;
+        let reflected = (0..n)
+            .map(|_| {
+                let r_in = Ray::new(Vec3::random_unit_vector() * 10.0, Vec3::random(), 0.0);
+                let mut rec = HitRecord::new();
+                rec.normal = Vec3::new(0.0, 0.0, 1.0);
+                rec.p = Vec3::random();
+                let mut srec = ScatterRecord::new();
+                assert!(mixed.scatter(&r_in, &mut rec, &mut srec));
+                let pdf = srec.pdf_ptr.clone().unwrap();
+                let scattered = Ray::new(rec.p, pdf.generate(), 0.0);
+                mixed.eval(&r_in, &rec, &srec, &scattered).x / pdf.value(&scattered.direction())
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((reflected - 0.25).abs() < 0.01, "{}", reflected);
+
+        let light: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(DiffuseLight::new1(Color1::new(4.0, 4.0, 4.0))));
+        let glowing = MixMaterial::new(white, light, mask);
+        let p = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(glowing.emitted(0.0, 0.0, &p), Color1::new(1.0, 1.0, 1.0));
+    }
+
+    /// A height that rises along `u`.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: &Point3) -> Color1 {
+            Color1::new(u, u, u)
+        }
+    }
+
+    #[test]
+    fn test_normal_and_bump_maps() {
+        let gray: Option<Arc<dyn Material + Send + Sync>> =
+            Some(Arc::new(Lambertian::new(&Color1::new(0.5, 0.5, 0.5))));
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = true;
+        rec.dpdu = Vec3::new(2.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 1.0, 0.0);
+        let close = |a: Vec3, b: Vec3| (a - b.unit_vector()).length() < 1e-6;
+
+        // The flat color leaves the normal alone; full red tilts it halfway towards dpdu.
+        let flat = NormalMap::new(
+            gray.clone(),
+            Some(Arc::new(SolidColor::new(Color1::new(0.5, 0.5, 1.0)))),
+        );
+        assert!(close(flat.shade(&rec).normal, Vec3::new(0.0, 0.0, 1.0)));
+        let tilted = NormalMap::new(
+            gray.clone(),
+            Some(Arc::new(SolidColor::new(Color1::new(1.0, 0.5, 1.0)))),
+        );
+        assert!(close(tilted.shade(&rec).normal, Vec3::new(1.0, 0.0, 1.0)));
+
+        // Seen from behind, the normal is perturbed the same way but faces the ray.
+        let mut back = rec.clone();
+        back.normal = -rec.normal;
+        back.front_face = false;
+        assert!(close(
+            tilted.shade(&back).normal,
+            Vec3::new(-1.0, 0.0, -1.0)
+        ));
+
+        // A height rising 0.5 per unit of u, which spans 2 units along x: a slope of 1/4.
+        let bumped = BumpMap::new(gray, Some(Arc::new(Ramp)), 0.5);
+        assert!(close(bumped.shade(&rec).normal, Vec3::new(-0.25, 0.0, 1.0)));
+
+        // Light arriving under the surface does not leak through the tilted normal.
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut srec = ScatterRecord::new();
+        assert!(tilted.scatter(&r_in, &mut rec.clone(), &mut srec));
+        let below = Ray::new(rec.p, Vec3::new(1.0, 0.0, -0.2), 0.0);
+        assert_eq!(
+            tilted.eval(&r_in, &rec, &srec, &below),
+            Color1::new(0.0, 0.0, 0.0)
+        );
+        let above = Ray::new(rec.p, Vec3::new(1.0, 0.0, 0.2), 0.0);
+        assert!(tilted.eval(&r_in, &rec, &srec, &above).x > 0.0);
+    }
+
+    #[test]
+    fn test_principled() {
+        // Plastic, brushed metal, frosted glass and lacquered velvet: every layering keeps
+        // energy, and the lobe mixture is sampled with the density `value` reports.
+        let scalar = |value: f64| -> Option<Arc<dyn Texture + Send + Sync>> {
+            Some(Arc::new(SolidColor::scalar(value)))
+        };
+        let base = || scalar(0.8);
+        let materials = [
+            Principled::new(base()),
+            Principled {
+                metallic: scalar(1.0),
+                roughness: scalar(0.3),
+                ..Principled::new(base())
+            },
+            Principled {
+                transmission: scalar(1.0),
+                roughness: scalar(0.6),
+                ..Principled::new(base())
+            },
+            Principled {
+                roughness: scalar(0.9),
+                sheen: scalar(1.0),
+                clearcoat: scalar(1.0),
+                clearcoat_gloss: scalar(0.5),
+                ..Principled::new(base())
+            },
+        ];
+        for (i, material) in materials.iter().enumerate() {
+            for wo in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.6, 0.0, 0.8)] {
+                let (sampled, uniform) = albedo(material, &wo, true);
+                assert!(sampled > 0.2 && sampled <= 1.01, "{}: {}", i, sampled);
+                assert!(
+                    (sampled - uniform).abs() < 0.05,
+                    "{}: {} vs {}",
+                    i,
+                    sampled,
+                    uniform
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::aabb::Aabb;
+use crate::bvh::{split, surrounding, BuildItem, SplitMethod};
+use crate::triangle;
+use crate::{Color1, HitRecord, Hittable, Material, Point3, Ray, Vec3};
+use std::sync::Arc;
+
+/// Largest number of triangles stored in one BVH leaf.
+const LEAF_SIZE: usize = 4;
+/// Deepest the BVH gets; the traversal stack has this many entries, and the builder makes a
+/// leaf of whatever is left at this depth.
+const MAX_DEPTH: usize = 64;
+
+/// Shared-vertex triangle data: normals, texture coordinates and colors, when present,
+/// are indexed like `positions`.
+#[derive(Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    pub colors: Option<Vec<Color1>>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl MeshData {
+    /// Checks that every attribute has one entry per vertex and every index is in range.
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.positions.len();
+        if let Some(normals) = &self.normals {
+            if normals.len() != n {
+                return Err(format!("{} normals for {} vertices", normals.len(), n));
+            }
+        }
+        if let Some(uvs) = &self.uvs {
+            if uvs.len() != n {
+                return Err(format!("{} uvs for {} vertices", uvs.len(), n));
+            }
+        }
+        if let Some(colors) = &self.colors {
+            if colors.len() != n {
+                return Err(format!("{} colors for {} vertices", colors.len(), n));
+            }
+        }
+        for (i, face) in self.indices.iter().enumerate() {
+            if let Some(index) = face.iter().find(|&&index| index as usize >= n) {
+                return Err(format!(
+                    "triangle {} uses vertex {} but there are only {} vertices",
+                    i, index, n
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A BVH node. Leaves (`count > 0`) own `triangles[offset..offset + count]`;
+/// interior nodes keep their left child right after themselves and the right child at `offset`.
+#[derive(Clone)]
+struct MeshNode {
+    bbox: Aabb,
+    offset: usize,
+    count: usize,
+}
+
+/// An indexed triangle mesh with its own flat BVH, so large meshes do not need
+/// one `Arc<dyn Hittable>` per triangle.
+pub struct TriangleMesh {
+    data: MeshData,
+    nodes: Vec<MeshNode>,
+    mat_ptr: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl TriangleMesh {
+    /// Builds the BVH over `data`, which must pass `MeshData::validate`.
+    pub fn new(mut data: MeshData, m: Option<Arc<dyn Material + Send + Sync>>) -> Self {
+        if let Err(e) = data.validate() {
+            panic!("invalid triangle mesh: {}", e);
+        }
+
+        let mut items: Vec<BuildItem<u32>> = data
+            .indices
+            .iter()
+            .enumerate()
+            .map(|(i, &[a, b, c])| {
+                let bbox = triangle::bounding_box(
+                    &data.positions[a as usize],
+                    &data.positions[b as usize],
+                    &data.positions[c as usize],
+                );
+                BuildItem::with_bounds(i as u32, bbox)
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(2 * items.len() / LEAF_SIZE + 1);
+        if !items.is_empty() {
+            TriangleMesh::build(&mut nodes, &mut items, 0, 1);
+        }
+        data.indices = items
+            .iter()
+            .map(|item| data.indices[item.object as usize])
+            .collect();
+
+        Self {
+            data,
+            nodes,
+            mat_ptr: m,
+        }
+    }
+
+    /// Splits `items` with the same SAH as scene BVHs; returns the node index.
+    fn build(
+        nodes: &mut Vec<MeshNode>,
+        items: &mut [BuildItem<u32>],
+        offset: usize,
+        depth: usize,
+    ) -> usize {
+        let bbox = surrounding(items);
+        let index = nodes.len();
+        if items.len() <= LEAF_SIZE || depth == MAX_DEPTH {
+            nodes.push(MeshNode {
+                bbox,
+                offset,
+                count: items.len(),
+            });
+            return index;
+        }
+
+        let (mid, _) = split(items, SplitMethod::Sah);
+        nodes.push(MeshNode {
+            bbox,
+            offset: 0,
+            count: 0,
+        });
+        let (left, right) = items.split_at_mut(mid);
+        TriangleMesh::build(nodes, left, offset, depth + 1);
+        let right_index = TriangleMesh::build(nodes, right, offset + mid, depth + 1);
+        nodes[index].offset = right_index;
+        index
+    }
+
+    fn vertex(&self, i: u32) -> &Point3 {
+        &self.data.positions[i as usize]
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let mut closest_so_far = t_max;
+        let mut closest = None;
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut sp = 1;
+        while sp > 0 {
+            sp -= 1;
+            let index = stack[sp];
+            let node = &self.nodes[index];
+            if !node.bbox.hit(r, t_min, closest_so_far) {
+                continue;
+            }
+            if node.count > 0 {
+                for tri in node.offset..node.offset + node.count {
+                    let [a, b, c] = self.data.indices[tri];
+                    if let Some((t, b1, b2)) = triangle::intersect(
+                        r,
+                        self.vertex(a),
+                        self.vertex(b),
+                        self.vertex(c),
+                        t_min,
+                        closest_so_far,
+                    ) {
+                        closest_so_far = t;
+                        closest = Some((tri, b1, b2));
+                    }
+                }
+            } else {
+                stack[sp] = node.offset;
+                stack[sp + 1] = index + 1;
+                sp += 2;
+            }
+        }
+
+        let (tri, b1, b2) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let [a, b, c] = self.data.indices[tri];
+        let normals = self
+            .data
+            .normals
+            .as_ref()
+            .map(|n| [&n[a as usize], &n[b as usize], &n[c as usize]]);
+        let uvs = self
+            .data
+            .uvs
+            .as_ref()
+            .map(|uv| [uv[a as usize], uv[b as usize], uv[c as usize]]);
+        let colors = self
+            .data
+            .colors
+            .as_ref()
+            .map(|col| [&col[a as usize], &col[b as usize], &col[c as usize]]);
+        triangle::fill_record(
+            r,
+            closest_so_far,
+            (b1, b2),
+            [self.vertex(a), self.vertex(b), self.vertex(c)],
+            normals,
+            uvs,
+            colors,
+            rec,
+        );
+        rec.mat_ptr = self.mat_ptr.clone();
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        match self.nodes.first() {
+            Some(root) => {
+                *output_box = root.bbox.clone();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::rtweekend::random_f64_1;
+    use crate::triangle::Triangle;
+
+    /// A bumpy grid of `n * n` quads.
+    fn grid(n: u32) -> MeshData {
+        let mut data = MeshData::default();
+        for j in 0..=n {
+            for i in 0..=n {
+                let h = ((i * 7 + j * 13) % 5) as f64 * 0.1;
+                data.positions.push(Point3::new(i as f64, h, j as f64));
+            }
+        }
+        for j in 0..n {
+            for i in 0..n {
+                let a = j * (n + 1) + i;
+                data.indices.push([a, a + 1, a + n + 1]);
+                data.indices.push([a + 1, a + n + 2, a + n + 1]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_triangle_hit() {
+        let tri = Triangle::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            None,
+            None,
+            None,
+        );
+        let mut rec = HitRecord::new();
+        let r = Ray::new(Point3::new(0.25, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(tri.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+        assert!(rec.front_face);
+        assert_eq!(rec.dpdu, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(rec.dpdv, Vec3::new(0.0, 1.0, 0.0));
+
+        // Texture coordinates stretched 1/2 along x and 1/4 along y.
+        let tri = Triangle::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            None,
+            Some([(0.0, 0.0), (0.5, 0.0), (0.0, 0.25)]),
+            None,
+        );
+        assert!(tri.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((rec.dpdv - Vec3::new(0.0, 4.0, 0.0)).length() < 1e-12);
+
+        let miss = Ray::new(Point3::new(0.75, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(!tri.hit(&miss, 0.001, f64::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_mesh_matches_triangles() {
+        let data = grid(16);
+        let mut list = HittableList::new();
+        for &[a, b, c] in &data.indices {
+            list.add(Some(Arc::new(Triangle::new(
+                [a, b, c].map(|i| data.positions[i as usize]),
+                None,
+                None,
+                None,
+            ))));
+        }
+        let mesh = TriangleMesh::new(data, None);
+        assert_eq!(mesh.data.indices.len(), 512);
+
+        for _ in 0..1000 {
+            let origin = Point3::new(random_f64_1(-2.0, 18.0), 5.0, random_f64_1(-2.0, 18.0));
+            let target = Point3::new(random_f64_1(0.0, 16.0), 0.0, random_f64_1(0.0, 16.0));
+            let r = Ray::new(origin, target - origin, 0.0);
+            let mut expected = HitRecord::new();
+            let mut actual = HitRecord::new();
+            let hit = list.hit(&r, 0.001, f64::INFINITY, &mut expected);
+            assert_eq!(hit, mesh.hit(&r, 0.001, f64::INFINITY, &mut actual));
+            if hit {
+                assert!((expected.t - actual.t).abs() < 1e-9);
+            }
+        }
+    }
+}
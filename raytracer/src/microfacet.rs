@@ -0,0 +1,521 @@
+//! The GGX (Trowbridge-Reitz) microfacet distribution with Smith masking, and Fresnel terms.
+//! Directions are given in a shading frame whose z axis is the surface normal.
+
+use crate::vec3::{Color1, Vec3};
+use std::f64::consts::PI;
+
+/// Below this `alpha` a surface is treated as perfectly smooth.
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// Isotropic GGX normal distribution of width `alpha`.
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    /// From a perceptual roughness in `[0, 1]`; `alpha` is its square, as in the Disney BRDF.
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// Whether the distribution is too narrow to sample, and the surface should reflect and
+    /// refract specularly instead.
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacet normals `wm` per unit projected area.
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z * wm.z;
+        let alpha2 = self.alpha * self.alpha;
+        let denom = cos2 * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * denom * denom)
+    }
+
+    /// Smith's auxiliary function: the masked microfacet area per visible area from `w`.
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of the microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`, height-correlated.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `wo`, for directions sampled by `sample_wm`.
+    pub fn pdf(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z.abs() * self.d(wm) * (*wo * *wm).max(0.0)
+    }
+
+    /// A microfacet normal visible from `wo`, with Heitz's "Sampling the GGX Distribution of
+    /// Visible Normals": sample the projected hemisphere of the stretched configuration.
+    pub fn sample_wm(&self, wo: &Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        let wo = if wo.z < 0.0 { -*wo } else { *wo };
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit_vector();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+}
+
+/// `w` mirrored around `n`, both pointing away from the surface.
+pub fn reflect(w: &Vec3, n: &Vec3) -> Vec3 {
+    *n * (2.0 * (*w * *n)) - *w
+}
+
+/// `w` refracted through a surface with normal `n` on its side, into a medium whose index of
+/// refraction relative to that side is `eta`; `None` on total internal reflection.
+pub fn refract(w: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = *w * *n;
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*w / eta + *n * (cos_i / eta - cos_t))
+}
+
+/// Unpolarized Fresnel reflectance of a boundary into a medium of relative index of
+/// refraction `eta`, for light arriving at `cos_theta` to the normal on the near side.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// GGX reflection and transmission at a rough boundary between dielectrics, after Walter et
+/// al., "Microfacet Models for Refraction through Rough Surfaces". Directions are in the
+/// frame of the side `wo` is on, so `wo.z > 0`, and `eta` is the index of refraction of the
+/// other side relative to this one.
+///
+/// Like the smooth `Dielectric`, transmission does not scale radiance by `1 / eta^2`; the
+/// factors cancel for light that enters an object and leaves it again.
+#[derive(Copy, Clone, Debug)]
+pub struct RoughDielectricBsdf {
+    pub distribution: TrowbridgeReitz,
+    pub eta: f64,
+}
+
+impl RoughDielectricBsdf {
+    /// The microfacet normal that turns `wo` into `wi`, facing `wo`, or `None` for pairs no
+    /// front-facing microfacet connects.
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let eta_p = if wi.z > 0.0 { 1.0 } else { self.eta };
+        let wm = *wi * eta_p + *wo;
+        if wi.z == 0.0 || wo.z <= 0.0 || wm.length_squared() == 0.0 {
+            return None;
+        }
+        let wm = wm.unit_vector();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+        if (wm * *wi) * wi.z < 0.0 || wm * *wo < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+
+    /// Squared denominator of the Jacobian of refraction, `(wi.wm + wo.wm / eta)^2`.
+    fn refraction_denominator(&self, wo: &Vec3, wi: &Vec3, wm: &Vec3) -> f64 {
+        let d = *wi * *wm + (*wo * *wm) / self.eta;
+        d * d
+    }
+
+    /// The BSDF, without the cosine.
+    pub fn f(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let Some(wm) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let fresnel = fresnel_dielectric(*wo * wm, self.eta);
+        let dg = self.distribution.d(&wm) * self.distribution.g(wo, wi);
+        if wi.z > 0.0 {
+            dg * fresnel / (4.0 * wi.z * wo.z)
+        } else {
+            let denom = self.refraction_denominator(wo, wi, &wm) * wi.z * wo.z;
+            dg * (1.0 - fresnel) * ((*wi * wm) * (*wo * wm) / denom).abs()
+        }
+    }
+
+    /// Density of `sample` producing `wi`.
+    pub fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let Some(wm) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let reflectance = fresnel_dielectric(*wo * wm, self.eta);
+        let pdf_wm = self.distribution.pdf(wo, &wm);
+        if wi.z > 0.0 {
+            pdf_wm / (4.0 * (*wo * wm).abs()) * reflectance
+        } else {
+            let dwm_dwi = (*wi * wm).abs() / self.refraction_denominator(wo, wi, &wm);
+            pdf_wm * dwm_dwi * (1.0 - reflectance)
+        }
+    }
+
+    /// Samples a visible microfacet normal with `u`, then reflects off it with the Fresnel
+    /// probability, decided by `uc`, or refracts through it. `None` when the direction ends
+    /// up on the wrong side.
+    pub fn sample(&self, wo: &Vec3, u: (f64, f64), uc: f64) -> Option<Vec3> {
+        let wm = self.distribution.sample_wm(wo, u);
+        let reflectance = fresnel_dielectric(*wo * wm, self.eta);
+        if uc < reflectance {
+            let wi = reflect(wo, &wm);
+            (wi.z > 0.0).then_some(wi)
+        } else {
+            let wi = refract(wo, &wm, self.eta)?;
+            (wi.z < 0.0).then_some(wi)
+        }
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex index of refraction
+/// `eta + i k`, for light arriving at `cos_theta` to the normal.
+pub fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rp + rs) / 2.0
+}
+
+/// `fresnel_conductor` for each channel.
+pub fn fresnel_conductor_rgb(cos_theta: f64, eta: &Color1, k: &Color1) -> Color1 {
+    Color1::new(
+        fresnel_conductor(cos_theta, eta.x, k.x),
+        fresnel_conductor(cos_theta, eta.y, k.y),
+        fresnel_conductor(cos_theta, eta.z, k.z),
+    )
+}
+
+/// Schlick's `(1 - cos)^5`, the weight of the grazing-angle term of his Fresnel approximation.
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn lerp_color(t: f64, a: Color1, b: Color1) -> Color1 {
+    a + (b - a) * t
+}
+
+/// Berry's distribution (GTR with gamma = 1) of width `alpha`, whose long tail gives the
+/// clearcoat its haze.
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta * cos_theta))
+}
+
+/// Roughness the principled lobes are clamped to, so they stay glossy enough to sample.
+const MIN_PRINCIPLED_ROUGHNESS: f64 = 0.05;
+
+/// The Disney "principled" BSDF (Burley 2012 and 2015), with its parameters already looked
+/// up at a hit. Directions are in the frame of the side `wo` is on, so `wo.z > 0`.
+///
+/// Four lobes are summed: a diffuse lobe with retro-reflection and sheen, weighted by
+/// `(1 - metallic) (1 - transmission)`; a GGX specular lobe whose color at normal incidence
+/// blends from `0.08 specular` for dielectrics to `base_color` for metals; a rough glass
+/// lobe of relative index of refraction `eta`, weighted by `(1 - metallic) transmission`
+/// and taking the specular lobe's place; and a white GTR1 clearcoat on top.
+#[derive(Copy, Clone, Debug)]
+pub struct PrincipledBsdf {
+    pub base_color: Color1,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+    pub eta: f64,
+}
+
+impl PrincipledBsdf {
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.max(MIN_PRINCIPLED_ROUGHNESS))
+    }
+
+    fn glass(&self) -> RoughDielectricBsdf {
+        RoughDielectricBsdf {
+            distribution: self.distribution(),
+            eta: self.eta,
+        }
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        lerp(self.clearcoat_gloss.clamp(0.0, 1.0), 0.1, 0.001)
+    }
+
+    /// Weights of the diffuse, specular, glass and clearcoat lobes.
+    fn weights(&self) -> [f64; 4] {
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let transmission = self.transmission.clamp(0.0, 1.0);
+        let dielectric = 1.0 - metallic;
+        [
+            dielectric * (1.0 - transmission),
+            1.0 - dielectric * transmission,
+            dielectric * transmission,
+            0.25 * self.clearcoat.max(0.0),
+        ]
+    }
+
+    /// Probabilities of sampling each lobe, in proportion to its weight.
+    fn probabilities(&self) -> [f64; 4] {
+        let weights = self.weights();
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    /// `base_color` with its luminance taken out, which the tint parameters blend towards.
+    fn tint(&self) -> Color1 {
+        let luminance = crate::film::luminance(&self.base_color);
+        if luminance > 0.0 {
+            self.base_color / luminance
+        } else {
+            Color1::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    /// The BSDF, without the cosine.
+    pub fn f(&self, wo: &Vec3, wi: &Vec3) -> Color1 {
+        let [diffuse_weight, specular_weight, glass_weight, clearcoat_weight] = self.weights();
+        let white = Color1::new(1.0, 1.0, 1.0);
+        let mut f = Color1::new(0.0, 0.0, 0.0);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return f;
+        }
+        if glass_weight > 0.0 {
+            let glass = self.glass().f(wo, wi) * glass_weight;
+            f += if wi.z > 0.0 {
+                white * glass
+            } else {
+                // Light crossing a surface twice is tinted by the base color overall.
+                let c = self.base_color;
+                Color1::new(c.x.sqrt(), c.y.sqrt(), c.z.sqrt()) * glass
+            };
+        }
+        if wi.z < 0.0 {
+            return f;
+        }
+
+        let wm = (*wo + *wi).unit_vector();
+        let cos_d = *wi * wm;
+        let (fo, fi, fd) = (
+            schlick_weight(wo.z),
+            schlick_weight(wi.z),
+            schlick_weight(cos_d),
+        );
+        let tint = self.tint();
+
+        if diffuse_weight > 0.0 {
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let retro = (1.0 + (fd90 - 1.0) * fo) * (1.0 + (fd90 - 1.0) * fi);
+            let sheen = lerp_color(self.sheen_tint, white, tint) * (self.sheen * fd);
+            f += (self.base_color * (retro / PI) + sheen) * diffuse_weight;
+        }
+
+        let denom = 4.0 * wo.z * wi.z;
+        if specular_weight > 0.0 {
+            let dielectric_f0 =
+                lerp_color(self.specular_tint, white, tint) * (0.08 * self.specular);
+            let f0 = lerp_color(self.metallic, dielectric_f0, self.base_color);
+            let fresnel = f0 + (white - f0) * schlick_weight(*wo * wm);
+            let distribution = self.distribution();
+            let dg = distribution.d(&wm) * distribution.g(wo, wi);
+            f += fresnel * (specular_weight * dg / denom);
+        }
+
+        if clearcoat_weight > 0.0 {
+            let fresnel = lerp(fd, 0.04, 1.0);
+            let masking = TrowbridgeReitz::from_roughness(0.5);
+            let d = gtr1(wm.z, self.clearcoat_alpha());
+            let g = masking.g1(wo) * masking.g1(wi);
+            f += white * (clearcoat_weight * d * fresnel * g / denom);
+        }
+        f
+    }
+
+    /// Density of `sample` producing `wi`: the density of each lobe, weighted by how often
+    /// it is picked.
+    pub fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let [diffuse, specular, glass, clearcoat] = self.probabilities();
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        if glass > 0.0 {
+            pdf += glass * self.glass().pdf(wo, wi);
+        }
+        if wi.z <= 0.0 {
+            return pdf;
+        }
+        let wm = (*wo + *wi).unit_vector();
+        let jacobian = 4.0 * (*wo * wm).abs();
+        pdf += diffuse * wi.z / PI;
+        pdf += specular * self.distribution().pdf(wo, &wm) / jacobian;
+        if clearcoat > 0.0 {
+            pdf += clearcoat * gtr1(wm.z, self.clearcoat_alpha()) * wm.z / jacobian;
+        }
+        pdf
+    }
+
+    /// Picks a lobe with `uc` and samples it with `u`; `None` when the direction ends up on
+    /// the wrong side.
+    pub fn sample(&self, wo: &Vec3, u: (f64, f64), uc: f64) -> Option<Vec3> {
+        let [diffuse, specular, glass, _] = self.probabilities();
+        if uc < diffuse {
+            let r = u.0.sqrt();
+            let phi = 2.0 * PI * u.1;
+            return Some(Vec3::new(
+                r * phi.cos(),
+                r * phi.sin(),
+                (1.0 - u.0).max(0.0).sqrt(),
+            ));
+        }
+        let uc = uc - diffuse;
+        let wm = if uc < specular {
+            self.distribution().sample_wm(wo, u)
+        } else if uc < specular + glass {
+            // Reuse what is left of `uc` for the choice between reflection and refraction.
+            let uc = ((uc - specular) / glass).clamp(0.0, 1.0);
+            return self.glass().sample(wo, u, uc);
+        } else {
+            let alpha2 = self.clearcoat_alpha().powi(2);
+            let cos2 = (1.0 - alpha2.powf(1.0 - u.0)) / (1.0 - alpha2);
+            let sin = (1.0 - cos2).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.1;
+            Vec3::new(sin * phi.cos(), sin * phi.sin(), cos2.sqrt())
+        };
+        let wi = reflect(wo, &wm);
+        (wi.z > 0.0).then_some(wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::random_f64;
+
+    #[test]
+    fn test_ggx_normalized() {
+        for roughness in [0.5, 0.8, 1.0] {
+            let distribution = TrowbridgeReitz::from_roughness(roughness);
+            let n = 400_000;
+            // Projected microfacet area integrates to one over the hemisphere.
+            let projected: f64 = (0..n)
+                .map(|_| {
+                    let w = Vec3::random_unit_vector();
+                    if w.z > 0.0 {
+                        distribution.d(&w) * w.z
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f64>()
+                * 4.0
+                * PI
+                / n as f64;
+            assert!(
+                (projected - 1.0).abs() < 0.05,
+                "{}: {}",
+                roughness,
+                projected
+            );
+
+            // Visible normals are a density, and sampling them agrees with it.
+            let wo = Vec3::new(0.6, 0.0, 0.8);
+            let visible: f64 = (0..n)
+                .map(|_| {
+                    let w = Vec3::random_unit_vector();
+                    if w.z > 0.0 {
+                        distribution.pdf(&wo, &w)
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f64>()
+                * 4.0
+                * PI
+                / n as f64;
+            assert!((visible - 1.0).abs() < 0.05, "{}: {}", roughness, visible);
+            let mean_z = (0..20_000)
+                .map(|_| distribution.sample_wm(&wo, (random_f64(), random_f64())).z)
+                .sum::<f64>()
+                / 20_000.0;
+            let expected_z = (0..n)
+                .map(|_| {
+                    let w = Vec3::random_unit_vector();
+                    if w.z > 0.0 {
+                        distribution.pdf(&wo, &w) * w.z
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f64>()
+                * 4.0
+                * PI
+                / n as f64;
+            assert!(
+                (mean_z - expected_z).abs() < 0.02,
+                "{} vs {}",
+                mean_z,
+                expected_z
+            );
+        }
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // A conductor with k = 0 is a dielectric: ((eta - 1) / (eta + 1))^2 head on.
+        assert!((fresnel_conductor(1.0, 1.5, 0.0) - 0.04).abs() < 1e-12);
+        let f0 =
+            ((0.2f64 - 1.0).powi(2) + 3.9f64.powi(2)) / ((0.2f64 + 1.0).powi(2) + 3.9f64.powi(2));
+        assert!((fresnel_conductor(1.0, 0.2, 3.9) - f0).abs() < 1e-12);
+        // Reflectance goes to one at grazing angles.
+        assert!((fresnel_conductor(0.0, 0.2, 3.9) - 1.0).abs() < 1e-9);
+        assert!(fresnel_conductor(0.5, 0.2, 3.9) > 0.0 && fresnel_conductor(0.5, 0.2, 3.9) < 1.0);
+    }
+}
@@ -1,7 +1,8 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
+use crate::sphere::{get_sphere_tangents, get_sphere_uv};
 use crate::{material, Ray};
-use crate::{vec3, Point3};
+use crate::{vec3, Color1, Point3};
 use material::Material;
 use std::sync::Arc;
 pub use vec3::Vec3;
@@ -64,7 +65,10 @@ impl Hittable for MovingSphere {
         rec.p = r.at(rec.t);
         let outward_normal = (rec.p - self.center(r.time())) / self.radius;
         rec.set_face_normal(r, &outward_normal);
+        get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = get_sphere_tangents(&outward_normal, self.radius);
         rec.mat_ptr = self.mat_ptr.clone();
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
 
         true
     }
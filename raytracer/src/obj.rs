@@ -0,0 +1,622 @@
+//! Wavefront OBJ / MTL importer.
+//!
+//! Faces with more than three vertices are fan-triangulated, and every `(group, material)`
+//! pair becomes one `TriangleMesh`. MTL entries are mapped onto the materials we have:
+//! emissive (`Ke`) → `DiffuseLight`, with any of the PBR extension's `Pr`, `Pm`, `Ps`, `Pc`
+//! or `Pcr` → `Principled`, transparent (`d` < 1 or `illum` 4/6/7) → `Dielectric`, mirror-like
+//! (`illum` 3 or 5) → `Conductor`, anything else → `Lambertian`. A `map_Kd` is multiplied by
+//! `Kd` when both are given.
+
+use crate::hittable_list::HittableList;
+use crate::material::{Conductor, Dielectric, DiffuseLight, Lambertian, Material, Principled};
+use crate::mesh::{MeshData, TriangleMesh};
+use crate::texture::{ImageTecture, SolidColor, Texture, TintTexture};
+use crate::vec3::{Color1, Point3, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type MaterialPtr = Option<Arc<dyn Material + Send + Sync>>;
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub path: PathBuf,
+    /// 1-based line number, 0 when the error is not tied to a line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.path.display(), self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// A material as written in an MTL file, before it is mapped onto one of ours.
+#[derive(Clone)]
+pub struct MtlMaterial {
+    /// `None` when the file gives no `Kd`.
+    pub kd: Option<Color1>,
+    pub ks: Color1,
+    pub ke: Color1,
+    pub ns: f64,
+    pub ni: f64,
+    pub d: f64,
+    pub illum: i32,
+    pub map_kd: Option<PathBuf>,
+    /// `Pr`, `Pm`, `Ps`, `Pc` and `Pcr` of the PBR extension.
+    pub roughness: Option<f64>,
+    pub metallic: Option<f64>,
+    pub sheen: Option<f64>,
+    pub clearcoat: Option<f64>,
+    pub clearcoat_roughness: Option<f64>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            kd: None,
+            ks: Color1::new(0.0, 0.0, 0.0),
+            ke: Color1::new(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: 1.5,
+            d: 1.0,
+            illum: 2,
+            map_kd: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+        }
+    }
+}
+
+/// Diffuse color of materials without `Kd` or `map_Kd`.
+const DEFAULT_KD: Color1 = Color1 {
+    x: 0.8,
+    y: 0.8,
+    z: 0.8,
+};
+
+fn max_component(c: &Color1) -> f64 {
+    c.x.max(c.y).max(c.z)
+}
+
+impl MtlMaterial {
+    pub fn to_material(&self) -> Result<Arc<dyn Material + Send + Sync>, String> {
+        if max_component(&self.ke) > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new1(self.ke)));
+        }
+        let pbr = [
+            self.roughness,
+            self.metallic,
+            self.sheen,
+            self.clearcoat,
+            self.clearcoat_roughness,
+        ];
+        if pbr.iter().any(Option::is_some) {
+            let scalar = |value: f64| -> Option<Arc<dyn Texture + Send + Sync>> {
+                Some(Arc::new(SolidColor::scalar(value)))
+            };
+            let mut material = Principled::new(Some(self.diffuse_texture()?));
+            material.transmission = scalar(1.0 - self.d.clamp(0.0, 1.0));
+            material.ior = scalar(self.ni);
+            for (texture, value) in [
+                (&mut material.roughness, self.roughness),
+                (&mut material.metallic, self.metallic),
+                (&mut material.sheen, self.sheen),
+                (&mut material.clearcoat, self.clearcoat),
+                (
+                    &mut material.clearcoat_gloss,
+                    self.clearcoat_roughness.map(|r| 1.0 - r),
+                ),
+            ] {
+                if let Some(value) = value {
+                    *texture = scalar(value);
+                }
+            }
+            return Ok(Arc::new(material));
+        }
+        if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7) {
+            return Ok(Arc::new(Dielectric::new(self.ni)));
+        }
+        if matches!(self.illum, 3 | 5) {
+            // Phong exponent to a roughness in [0, 1]: Ns = 0 is fully rough, large Ns is a
+            // mirror. Beckmann's alpha = sqrt(2 / (Ns + 2)) is about GGX's, roughness squared.
+            let roughness = (2.0 / (self.ns + 2.0)).powf(0.25);
+            return Ok(Arc::new(Conductor::from_reflectance(&self.ks, roughness)));
+        }
+        Ok(Arc::new(Lambertian::new1(Some(self.diffuse_texture()?))))
+    }
+
+    /// `map_Kd` tinted by `Kd` when there is one, `Kd` otherwise.
+    fn diffuse_texture(&self) -> Result<Arc<dyn Texture + Send + Sync>, String> {
+        let path = match &self.map_kd {
+            Some(path) => path.to_string_lossy(),
+            None => return Ok(Arc::new(SolidColor::new(self.kd.unwrap_or(DEFAULT_KD)))),
+        };
+        let image = Arc::new(
+            ImageTecture::load(&path)
+                .map_err(|e| format!("cannot load texture {}: {}", path, e))?,
+        );
+        Ok(match self.kd {
+            Some(kd) => Arc::new(TintTexture::new(image, kd)),
+            None => image,
+        })
+    }
+}
+
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn float(&self, keyword: &str, token: Option<&str>) -> Result<f64, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("`{}` needs more numbers", keyword)))?;
+        token
+            .parse::<f64>()
+            .map_err(|_| self.error(format!("`{}`: `{}` is not a number", keyword, token)))
+    }
+
+    fn vec3<'b>(
+        &self,
+        keyword: &str,
+        tokens: &mut impl Iterator<Item = &'b str>,
+    ) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(
+            self.float(keyword, tokens.next())?,
+            self.float(keyword, tokens.next())?,
+            self.float(keyword, tokens.next())?,
+        ))
+    }
+}
+
+/// Reads the materials of an MTL file. Texture paths are resolved against `base_dir`.
+pub fn parse_mtl(
+    text: &str,
+    path: &Path,
+    base_dir: &Path,
+) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    let mut parser = LineParser { path, line: 0 };
+
+    for (number, line) in text.lines().enumerate() {
+        parser.line = number + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens
+                .next()
+                .ok_or_else(|| parser.error("`newmtl` needs a name"))?;
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name.to_string(), MtlMaterial::default()));
+            continue;
+        }
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => return Err(parser.error(format!("`{}` before any `newmtl`", keyword))),
+        };
+        match keyword {
+            "Kd" => material.kd = Some(parser.vec3(keyword, &mut tokens)?),
+            "Ks" => material.ks = parser.vec3(keyword, &mut tokens)?,
+            "Ke" => material.ke = parser.vec3(keyword, &mut tokens)?,
+            "Ns" => material.ns = parser.float(keyword, tokens.next())?,
+            "Ni" => material.ni = parser.float(keyword, tokens.next())?,
+            "d" => material.d = parser.float(keyword, tokens.next())?,
+            "Tr" => material.d = 1.0 - parser.float(keyword, tokens.next())?,
+            "illum" => material.illum = parser.float(keyword, tokens.next())? as i32,
+            "Pr" => material.roughness = Some(parser.float(keyword, tokens.next())?),
+            "Pm" => material.metallic = Some(parser.float(keyword, tokens.next())?),
+            "Ps" => material.sheen = Some(parser.float(keyword, tokens.next())?),
+            "Pc" => material.clearcoat = Some(parser.float(keyword, tokens.next())?),
+            "Pcr" => material.clearcoat_roughness = Some(parser.float(keyword, tokens.next())?),
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` come first; the file name is the last token.
+                let file = tokens
+                    .last()
+                    .ok_or_else(|| parser.error("`map_Kd` needs a file name"))?;
+                material.map_kd = Some(base_dir.join(file));
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+/// One vertex of a face: indices into positions, texture coordinates and normals.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Triangles collected for one `(group, material)` pair, with deduplicated vertices.
+#[derive(Default)]
+struct MeshBuilder {
+    data: MeshData,
+    corners: HashMap<Corner, u32>,
+    has_normals: bool,
+    has_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new() -> Self {
+        Self {
+            has_normals: true,
+            has_uvs: true,
+            ..Default::default()
+        }
+    }
+
+    fn vertex(&mut self, corner: Corner, obj: &ObjData) -> u32 {
+        if let Some(&index) = self.corners.get(&corner) {
+            return index;
+        }
+        let (v, vt, vn) = corner;
+        let index = self.data.positions.len() as u32;
+        self.data.positions.push(obj.positions[v]);
+        match vt {
+            Some(vt) => self.uvs().push(obj.uvs[vt]),
+            None => self.has_uvs = false,
+        }
+        match vn {
+            Some(vn) => self.normals().push(obj.normals[vn]),
+            None => self.has_normals = false,
+        }
+        self.corners.insert(corner, index);
+        index
+    }
+
+    fn uvs(&mut self) -> &mut Vec<(f64, f64)> {
+        self.data.uvs.get_or_insert_with(Vec::new)
+    }
+
+    fn normals(&mut self) -> &mut Vec<Vec3> {
+        self.data.normals.get_or_insert_with(Vec::new)
+    }
+
+    /// Drops attributes that only some vertices have.
+    fn finish(mut self) -> MeshData {
+        if !self.has_uvs {
+            self.data.uvs = None;
+        }
+        if !self.has_normals {
+            self.data.normals = None;
+        }
+        self.data
+    }
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Point3>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+}
+
+/// Resolves a 1-based (or negative, relative) OBJ index against `len` elements.
+fn resolve_index(token: &str, len: usize, parser: &LineParser) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| parser.error(format!("`{}` is not a valid index", token)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(parser.error(format!(
+            "index {} is out of range, {} elements are defined",
+            index, len
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_corner(token: &str, obj: &ObjData, parser: &LineParser) -> Result<Corner, ObjError> {
+    let mut parts = token.split('/');
+    let v = resolve_index(parts.next().unwrap_or(""), obj.positions.len(), parser)?;
+    let vt = match parts.next() {
+        Some("") | None => None,
+        Some(t) => Some(resolve_index(t, obj.uvs.len(), parser)?),
+    };
+    let vn = match parts.next() {
+        Some("") | None => None,
+        Some(t) => Some(resolve_index(t, obj.normals.len(), parser)?),
+    };
+    if parts.next().is_some() {
+        return Err(parser.error(format!("malformed face vertex `{}`", token)));
+    }
+    Ok((v, vt, vn))
+}
+
+/// Meshes of an OBJ file in order of first use, keyed by group and material name, and the
+/// MTL materials it loaded.
+type ObjMeshes = (
+    Vec<((String, Option<String>), MeshData)>,
+    HashMap<String, MtlMaterial>,
+);
+
+/// Parses OBJ text. `mtllib` files are read relative to `base_dir`; faces without a
+/// usable material get `default_material`.
+pub fn parse_obj(
+    text: &str,
+    path: &Path,
+    base_dir: &Path,
+    default_material: MaterialPtr,
+) -> Result<HittableList, ObjError> {
+    let (meshes, library) = read_meshes(text, path, base_dir)?;
+    let mut materials: HashMap<String, MaterialPtr> = HashMap::new();
+    let mut list = HittableList::new();
+    for ((_, name), mesh) in meshes {
+        let material = match name {
+            Some(name) if library.contains_key(&name) => {
+                if !materials.contains_key(&name) {
+                    let material = library[&name].to_material().map_err(|message| ObjError {
+                        path: path.to_path_buf(),
+                        line: 0,
+                        message: format!("material `{}`: {}", name, message),
+                    })?;
+                    materials.insert(name.clone(), Some(material));
+                }
+                materials[&name].clone()
+            }
+            _ => default_material.clone(),
+        };
+        list.add(Some(Arc::new(TriangleMesh::new(mesh, material))));
+    }
+    Ok(list)
+}
+
+/// Collects the faces of OBJ text into fan-triangulated meshes.
+fn read_meshes(text: &str, path: &Path, base_dir: &Path) -> Result<ObjMeshes, ObjError> {
+    let mut obj = ObjData::default();
+    let mut library: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut group = String::new();
+    let mut material_name: Option<String> = None;
+    // Meshes in order of first use, keyed by group and material name.
+    let mut meshes: Vec<((String, Option<String>), MeshBuilder)> = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut parser = LineParser { path, line: 0 };
+
+    for (number, line) in text.lines().enumerate() {
+        parser.line = number + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        match keyword {
+            "v" => obj.positions.push(parser.vec3(keyword, &mut tokens)?),
+            "vn" => obj.normals.push(parser.vec3(keyword, &mut tokens)?),
+            "vt" => {
+                let u = parser.float(keyword, tokens.next())?;
+                let v = match tokens.next() {
+                    Some(v) => parser.float(keyword, Some(v))?,
+                    None => 0.0,
+                };
+                obj.uvs.push((u, v));
+            }
+            "g" | "o" => {
+                group = tokens.collect::<Vec<_>>().join(" ");
+                current = None;
+            }
+            "usemtl" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| parser.error("`usemtl` needs a material name"))?;
+                material_name = Some(name.to_string());
+                current = None;
+            }
+            "mtllib" => {
+                for file in tokens {
+                    let mtl_path = base_dir.join(file);
+                    let text = std::fs::read_to_string(&mtl_path)
+                        .map_err(|e| parser.error(format!("cannot read {}: {}", file, e)))?;
+                    let dir = mtl_path.parent().unwrap_or(base_dir);
+                    library.extend(parse_mtl(&text, &mtl_path, dir)?);
+                }
+            }
+            "f" => {
+                let corners = tokens
+                    .map(|t| parse_corner(t, &obj, &parser))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parser.error(format!(
+                        "a face needs at least 3 vertices, got {}",
+                        corners.len()
+                    )));
+                }
+                let index = *current.get_or_insert_with(|| {
+                    let key = (group.clone(), material_name.clone());
+                    match meshes.iter().position(|(k, _)| *k == key) {
+                        Some(i) => i,
+                        None => {
+                            meshes.push((key, MeshBuilder::new()));
+                            meshes.len() - 1
+                        }
+                    }
+                });
+                let mesh = &mut meshes[index].1;
+                let first = mesh.vertex(corners[0], &obj);
+                for pair in corners[1..].windows(2) {
+                    let b = mesh.vertex(pair[0], &obj);
+                    let c = mesh.vertex(pair[1], &obj);
+                    mesh.data.indices.push([first, b, c]);
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not supported.
+            _ => {}
+        }
+    }
+
+    let meshes = meshes
+        .into_iter()
+        .map(|(key, mesh)| (key, mesh.finish()))
+        .collect();
+    Ok((meshes, library))
+}
+
+/// Loads an OBJ file and the MTL libraries it references.
+pub fn load_obj(path: &Path, default_material: MaterialPtr) -> Result<HittableList, ObjError> {
+    let text = std::fs::read_to_string(path).map_err(|e| ObjError {
+        path: path.to_path_buf(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(&text, path, base_dir, default_material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::ScatterRecord;
+    use crate::{HitRecord, Ray};
+
+    fn parse(text: &str) -> Result<HittableList, ObjError> {
+        parse_obj(
+            text,
+            Path::new("test.obj"),
+            Path::new("."),
+            Some(Arc::new(Lambertian::new(&Color1::new(0.5, 0.5, 0.5)))),
+        )
+    }
+
+    #[test]
+    fn test_groups_and_ngons() {
+        let list = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+             g quad\nf 1/1/1 2/2/1 3/3/1 4/4/1\n\
+             g tri\nf -4 -3 -2\n",
+        )
+        .unwrap();
+        assert_eq!(list.objects.len(), 2);
+
+        let (meshes, _) = read_meshes(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0.5 1.5 0\n\
+             g quad\nf 1 2 3 4\ng tri\nf -5 -4 -3\ng pentagon\nf 1 2 3 5 4\n",
+            Path::new("test.obj"),
+            Path::new("."),
+        )
+        .unwrap();
+        let triangles: Vec<(&str, usize)> = meshes
+            .iter()
+            .map(|((group, _), mesh)| (group.as_str(), mesh.indices.len()))
+            .collect();
+        assert_eq!(triangles, [("quad", 2), ("tri", 1), ("pentagon", 3)]);
+        assert_eq!(meshes[0].1.indices, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let err = parse("v 0 0 0\nv 1 0 0\nv 1 x 0\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:3: `v`: `x` is not a number");
+
+        let err = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.obj:4: index 4 is out of range, 3 elements are defined"
+        );
+
+        let err = parse("v 0 0 0\nv 1 0 0\nf 1 2\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.obj:3: a face needs at least 3 vertices, got 2"
+        );
+    }
+
+    #[test]
+    fn test_mtl() {
+        let materials = parse_mtl(
+            "newmtl glass\nNi 1.33\nd 0.2\n\nnewmtl lamp\nKe 4 4 4\nnewmtl skin\nmap_Kd -s 1 1 1 skin.png\n\
+             newmtl brass\nKd 0.8 0.6 0.2\nPm 1\nPr 0.3\n",
+            Path::new("test.mtl"),
+            Path::new("textures"),
+        )
+        .unwrap();
+        assert_eq!(materials["glass"].ni, 1.33);
+        assert_eq!(materials["brass"].metallic, Some(1.0));
+        assert_eq!(materials["brass"].roughness, Some(0.3));
+        assert!(materials["brass"].to_material().is_ok());
+        assert_eq!(materials["lamp"].ke, Color1::new(4.0, 4.0, 4.0));
+        assert_eq!(
+            materials["skin"].map_kd,
+            Some(Path::new("textures").join("skin.png"))
+        );
+
+        let err = parse_mtl("Kd 1 1 1\n", Path::new("test.mtl"), Path::new("."))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "test.mtl:1: `Kd` before any `newmtl`");
+    }
+
+    /// The attenuation `material` scatters a ray straight down onto the origin with.
+    fn attenuation(material: &MtlMaterial) -> Color1 {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        (rec.u, rec.v) = (0.25, 0.25);
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let mut srec = ScatterRecord::new();
+        assert!(material
+            .to_material()
+            .unwrap()
+            .scatter(&r, &mut rec, &mut srec));
+        srec.attenuation
+    }
+
+    #[test]
+    fn test_mtl_mapping() {
+        let dir = std::env::temp_dir().join(format!("raytracer-mtl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 255, 0]))
+            .save(dir.join("yellow.png"))
+            .unwrap();
+        let materials = parse_mtl(
+            "newmtl tinted\nKd 0.5 0.25 1\nmap_Kd yellow.png\n\
+             newmtl plain\nmap_Kd yellow.png\n\
+             newmtl glossy\nKd 0.2 0.2 0.2\nKs 0.9 0.9 0.9\nNs 10\n\
+             newmtl mirror\nKd 0.2 0.2 0.2\nKs 0.9 0.9 0.9\nNs 10\nillum 3\n",
+            Path::new("test.mtl"),
+            &dir,
+        )
+        .unwrap();
+        let tinted = attenuation(&materials["tinted"]);
+        let plain = attenuation(&materials["plain"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tinted, Color1::new(0.5, 0.25, 0.0));
+        assert_eq!(plain, Color1::new(1.0, 1.0, 0.0));
+
+        // A bright `Ks` alone keeps a material diffuse; `illum` 3 makes it a metal.
+        assert_eq!(
+            attenuation(&materials["glossy"]),
+            Color1::new(0.2, 0.2, 0.2)
+        );
+        assert_eq!(
+            attenuation(&materials["mirror"]),
+            Color1::new(1.0, 1.0, 1.0)
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis.
+pub struct Onb {
+    pub axis: [Vec3; 3],
+}
+
+impl Onb {
+    /// A basis whose `w` axis points along `n`.
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = n.unit_vector();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::cross(&w, &a).unit_vector();
+        let u = Vec3::cross(&w, &v);
+        Self { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    /// Coordinates of the world vector `a` in this basis.
+    pub fn project(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(*a * self.u(), *a * self.v(), *a * self.w())
+    }
+
+    /// Converts `a`, given in this basis, to world coordinates.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u() * a.x + self.v() * a.y + self.w() * a.z
+    }
+}
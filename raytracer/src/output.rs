@@ -0,0 +1,168 @@
+//! Writing the rendered radiance buffer to disk.
+//!
+//! Low dynamic range formats go through the display transform; `.hdr` and `.exr`
+//! store the linear radiance as it came out of the renderer.
+
+use crate::color::DisplayTransform;
+use crate::film::Film;
+use clap::ValueEnum;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageOutputFormat, Rgb};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Format of the rendered image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Jpeg,
+    /// 8-bit PNG
+    Png,
+    /// 16-bit PNG
+    Png16,
+    /// Binary PPM
+    Ppm,
+    /// Radiance RGBE, linear radiance
+    Hdr,
+    /// OpenEXR with 32-bit float channels, linear radiance
+    Exr,
+}
+
+impl OutputFormat {
+    /// Picks the format from the file extension; `.png` means 8-bit PNG.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        Some(match extension.as_str() {
+            "jpg" | "jpeg" => OutputFormat::Jpeg,
+            "png" => OutputFormat::Png,
+            "ppm" => OutputFormat::Ppm,
+            "hdr" => OutputFormat::Hdr,
+            "exr" => OutputFormat::Exr,
+            _ => return None,
+        })
+    }
+}
+
+/// Writes `film` to `path`, applying `display` to low dynamic range formats.
+pub fn save_image(
+    path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    film: &Film,
+    display: &DisplayTransform,
+) -> Result<(), ImageError> {
+    let (width, height) = (film.width(), film.height());
+    let (w, h) = (width as u32, height as u32);
+    let pixels = film.pixels();
+    let image = match format {
+        OutputFormat::Hdr => {
+            let data: Vec<Rgb<f32>> = pixels
+                .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+                .collect();
+            let file = BufWriter::new(File::create(path)?);
+            return HdrEncoder::new(file).encode(&data, width, height);
+        }
+        OutputFormat::Exr => {
+            let data = pixels
+                .flat_map(|c| [c.x as f32, c.y as f32, c.z as f32])
+                .collect();
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(w, h, data).unwrap())
+        }
+        OutputFormat::Png16 => {
+            let data = pixels.flat_map(|c| display.encode16(&c)).collect();
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(w, h, data).unwrap())
+        }
+        OutputFormat::Jpeg | OutputFormat::Png | OutputFormat::Ppm => {
+            let data = pixels.flat_map(|c| display.encode8(&c)).collect();
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, data).unwrap())
+        }
+    };
+    let output_format = match format {
+        OutputFormat::Jpeg => ImageOutputFormat::Jpeg(quality),
+        OutputFormat::Ppm => ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+        OutputFormat::Exr => ImageOutputFormat::OpenExr,
+        _ => ImageOutputFormat::Png,
+    };
+    let mut file = BufWriter::new(File::create(path)?);
+    image.write_to(&mut file, output_format)
+}
+
+/// Writes the number of samples each pixel received as a blue (fewest) to red (most) map,
+/// in the format given by the extension of `path`.
+pub fn save_heatmap(path: &Path, film: &Film) -> Result<(), ImageError> {
+    let (width, height) = (film.width(), film.height());
+    let counts = || (0..height).flat_map(|y| (0..width).map(move |x| film.samples(x, y)));
+    let max = counts().max().unwrap_or(0).max(1) as f64;
+    let data = counts()
+        .flat_map(|n| {
+            let t = n as f64 / max;
+            // The "jet" ramp: blue, cyan, green, yellow, red.
+            let channel =
+                |center: f64| (255.0 * (1.5 - (4.0 * t - center).abs()).clamp(0.0, 1.0)) as u8;
+            [channel(3.0), channel(2.0), channel(1.0)]
+        })
+        .collect();
+    let image: ImageBuffer<Rgb<u8>, Vec<u8>> =
+        ImageBuffer::from_raw(width as u32, height as u32, data).unwrap();
+    image.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::PixelSamples;
+    use crate::vec3::Color1;
+    use image::codecs::hdr::HdrDecoder;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!("raytracer-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut film = Film::new(2, 2);
+        let samples = |sum, count| PixelSamples {
+            sum,
+            luminance_sq: 0.0,
+            count,
+        };
+        film.add_samples(0, 0, &samples(Color1::new(0.0, 0.5, 2.0), 2));
+        film.add_samples(1, 0, &samples(Color1::new(4.0, 0.5, 0.0), 1));
+        film.add_samples(0, 1, &samples(Color1::new(1.0, 1.0, 1.0), 1));
+
+        for (name, format) in [
+            ("a.png", OutputFormat::Png),
+            ("a16.png", OutputFormat::Png16),
+            ("a.ppm", OutputFormat::Ppm),
+            ("a.hdr", OutputFormat::Hdr),
+            ("a.exr", OutputFormat::Exr),
+        ] {
+            let path = dir.join(name);
+            save_image(&path, format, 100, &film, &DisplayTransform::default()).unwrap();
+            let bright = if format == OutputFormat::Hdr {
+                // `image::open` converts Radiance files to 8 bits.
+                let file = std::io::BufReader::new(File::open(&path).unwrap());
+                HdrDecoder::new(file).unwrap().read_image_hdr().unwrap()[1]
+            } else {
+                let image = image::open(&path).unwrap();
+                assert_eq!((image.width(), image.height()), (2, 2));
+                *image.to_rgb32f().get_pixel(1, 0)
+            };
+            match format {
+                // Linear formats keep values above 1.
+                OutputFormat::Hdr | OutputFormat::Exr => {
+                    assert!((bright[0] - 4.0).abs() < 0.05, "{}: {:?}", name, bright)
+                }
+                _ => assert_eq!(bright[0], 1.0, "{}", name),
+            }
+        }
+
+        let path = dir.join("heat.png");
+        save_heatmap(&path, &film).unwrap();
+        let heat = image::open(&path).unwrap().to_rgb8();
+        // (0, 0) got the most samples and (1, 1) none.
+        let (most, none) = (heat.get_pixel(0, 0), heat.get_pixel(1, 1));
+        assert!(most[0] > 0 && most[2] == 0, "{:?}", most);
+        assert!(none[0] == 0 && none[2] > 0, "{:?}", none);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
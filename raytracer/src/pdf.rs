@@ -0,0 +1,258 @@
+use crate::microfacet::{reflect, PrincipledBsdf, RoughDielectricBsdf, TrowbridgeReitz};
+use crate::onb::Onb;
+use crate::sampler::{next_1d, next_2d};
+use crate::{Hittable, Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A probability density over directions, measured in solid angle.
+pub trait Pdf {
+    fn value(&self, direction: &Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+/// Cosine-weighted directions around a normal.
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> Self {
+        Self {
+            uvw: Onb::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = direction.unit_vector() * self.uvw.w();
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.local(&Vec3::random_cosine_direction())
+    }
+}
+
+/// Uniform directions over the whole sphere.
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+}
+
+/// Directions reflected off GGX microfacets seen from `wo`, with the normals sampled from
+/// the visible ones. `wo` points away from the surface whose normal is `uvw.w()`.
+pub struct MicrofacetReflectionPdf {
+    uvw: Onb,
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+}
+
+impl MicrofacetReflectionPdf {
+    pub fn new(normal: &Vec3, wo: &Vec3, distribution: TrowbridgeReitz) -> Self {
+        let uvw = Onb::build_from_w(normal);
+        Self {
+            wo: uvw.project(&wo.unit_vector()),
+            uvw,
+            distribution,
+        }
+    }
+}
+
+impl Pdf for MicrofacetReflectionPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.uvw.project(&direction.unit_vector());
+        if wi.z <= 0.0 || self.wo.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = (self.wo + wi).unit_vector();
+        self.distribution.pdf(&self.wo, &wm) / (4.0 * (self.wo * wm).abs())
+    }
+
+    fn generate(&self) -> Vec3 {
+        let wm = self.distribution.sample_wm(&self.wo, next_2d());
+        self.uvw.local(&reflect(&self.wo, &wm))
+    }
+}
+
+/// Directions reflected off or refracted through a rough dielectric boundary, seen from
+/// `wo` on the side of `uvw.w()`.
+pub struct MicrofacetTransmissionPdf {
+    uvw: Onb,
+    wo: Vec3,
+    bsdf: RoughDielectricBsdf,
+}
+
+impl MicrofacetTransmissionPdf {
+    pub fn new(normal: &Vec3, wo: &Vec3, bsdf: RoughDielectricBsdf) -> Self {
+        let uvw = Onb::build_from_w(normal);
+        Self {
+            wo: uvw.project(&wo.unit_vector()),
+            uvw,
+            bsdf,
+        }
+    }
+}
+
+impl Pdf for MicrofacetTransmissionPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.uvw.project(&direction.unit_vector());
+        self.bsdf.pdf(&self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let u = next_2d();
+        match self.bsdf.sample(&self.wo, u, next_1d()) {
+            Some(wi) => self.uvw.local(&wi),
+            // A direction in the surface, which `value` gives no density, so the path ends.
+            None => self.uvw.u(),
+        }
+    }
+}
+
+/// Directions scattered by the lobes of a principled BSDF, seen from `wo` on the side of
+/// `uvw.w()`.
+pub struct PrincipledPdf {
+    uvw: Onb,
+    wo: Vec3,
+    bsdf: PrincipledBsdf,
+}
+
+impl PrincipledPdf {
+    pub fn new(normal: &Vec3, wo: &Vec3, bsdf: PrincipledBsdf) -> Self {
+        let uvw = Onb::build_from_w(normal);
+        Self {
+            wo: uvw.project(&wo.unit_vector()),
+            uvw,
+            bsdf,
+        }
+    }
+}
+
+impl Pdf for PrincipledPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.uvw.project(&direction.unit_vector());
+        self.bsdf.pdf(&self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let u = next_2d();
+        match self.bsdf.sample(&self.wo, u, next_1d()) {
+            Some(wi) => self.uvw.local(&wi),
+            None => self.uvw.u(),
+        }
+    }
+}
+
+/// Directions from `o` towards an object, usually a light.
+pub struct HittablePdf {
+    o: Point3,
+    ptr: Option<Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl HittablePdf {
+    pub fn new(p: Option<Arc<dyn Hittable + Send + Sync>>, origin: &Point3) -> Self {
+        Self { o: *origin, ptr: p }
+    }
+}
+
+impl Pdf for HittablePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.ptr.clone().unwrap().pdf_value(&self.o, direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.ptr.clone().unwrap().random(&self.o)
+    }
+}
+
+/// Veach's power heuristic (beta = 2): the MIS weight of a sample drawn with density `pdf`
+/// when `other_pdf` could have produced it too.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XzRect;
+    use crate::sphere::Sphere;
+
+    /// Estimates the integral of `pdf` over the sphere of directions, which must be 1.
+    fn integrate(pdf: &dyn Pdf) -> f64 {
+        let n = 200_000;
+        let sum: f64 = (0..n).map(|_| pdf.value(&Vec3::random_unit_vector())).sum();
+        sum * 4.0 * PI / n as f64
+    }
+
+    /// `value` must be the density `generate` actually samples from: E[f / p] over
+    /// `generate` equals the integral of `f`, here for f = 1 on the object.
+    fn check(pdf: &dyn Pdf, solid_angle: f64) {
+        let n = 200_000;
+        let sum: f64 = (0..n)
+            .map(|_| {
+                let p = pdf.value(&pdf.generate());
+                if p > 0.0 {
+                    1.0 / p
+                } else {
+                    0.0
+                }
+            })
+            .sum();
+        let estimate = sum / n as f64;
+        assert!(
+            (estimate - solid_angle).abs() < 0.02 * solid_angle,
+            "{} vs {}",
+            estimate,
+            solid_angle
+        );
+    }
+
+    #[test]
+    fn test_pdfs_normalized() {
+        assert!((integrate(&CosinePdf::new(&Vec3::new(0.3, 1.0, -0.2))) - 1.0).abs() < 0.02);
+        assert!((integrate(&SpherePdf) - 1.0).abs() < 1e-9);
+        check(&CosinePdf::new(&Vec3::new(0.0, 0.0, 1.0)), 2.0 * PI);
+
+        // Rough reflections cover the whole hemisphere, less what goes below the surface.
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let wo = Vec3::new(0.5, 1.0, 0.0);
+        let glossy =
+            MicrofacetReflectionPdf::new(&normal, &wo, TrowbridgeReitz::from_roughness(0.6));
+        let total = integrate(&glossy);
+        assert!(total > 0.85 && total < 1.02, "{}", total);
+        check(&glossy, 2.0 * PI);
+    }
+
+    #[test]
+    fn test_light_pdfs() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        // A sphere of radius 1 at distance 2 subtends 2 pi (1 - cos 30deg).
+        let sphere = Sphere::new(Point3::new(0.0, 2.0, 0.0), 1.0, None);
+        let sphere_pdf = HittablePdf::new(Some(Arc::new(sphere)), &origin);
+        check(&sphere_pdf, 2.0 * PI * (1.0 - (3.0f64).sqrt() / 2.0));
+
+        // A small square far away subtends about area / distance^2.
+        let rect = XzRect::new(-0.1, 0.1, -0.1, 0.1, 10.0, None);
+        let rect_pdf = HittablePdf::new(Some(Arc::new(rect)), &origin);
+        check(&rect_pdf, 0.04 / 100.0);
+    }
+}
@@ -0,0 +1,477 @@
+//! PLY mesh reader for ASCII and binary (little / big endian) files.
+//!
+//! Reads `vertex` positions plus, when present, normals (`nx ny nz`), texture coordinates
+//! (`u v`, `s t` or `texture_u texture_v`) and colors (`red green blue`), and the
+//! `vertex_indices` lists of `face`. Polygons are fan-triangulated; other elements are skipped.
+
+use crate::mesh::MeshData;
+use crate::vec3::{Color1, Point3, Vec3};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct PlyError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// Integer color channels run up to the largest value of their type.
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::I8 => 1.0 / i8::MAX as f64,
+            ScalarType::U8 => 1.0 / u8::MAX as f64,
+            ScalarType::I16 => 1.0 / i16::MAX as f64,
+            ScalarType::U16 => 1.0 / u16::MAX as f64,
+            ScalarType::I32 => 1.0 / i32::MAX as f64,
+            ScalarType::U32 => 1.0 / u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    /// `Some(count type)` for list properties.
+    list: Option<ScalarType>,
+    scalar: ScalarType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// Byte offset of the body.
+    body: usize,
+}
+
+fn parse_header(bytes: &[u8]) -> Result<Header, String> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    let mut number = 0;
+
+    loop {
+        let end = match bytes[offset..].iter().position(|&b| b == b'\n') {
+            Some(end) => offset + end,
+            None => return Err("header is not terminated by `end_header`".to_string()),
+        };
+        let line = std::str::from_utf8(&bytes[offset..end])
+            .map_err(|_| format!("header line {} is not valid text", number + 1))?;
+        offset = end + 1;
+        number += 1;
+        let error = |message: String| format!("header line {}: {}", number, message);
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if number == 1 {
+            if tokens != ["ply"] {
+                return Err("not a PLY file, the first line must be `ply`".to_string());
+            }
+            continue;
+        }
+        match tokens.first().copied() {
+            Some("format") => {
+                format = Some(match tokens.get(1).copied() {
+                    Some("ascii") => Format::Ascii,
+                    Some("binary_little_endian") => Format::BinaryLittleEndian,
+                    Some("binary_big_endian") => Format::BinaryBigEndian,
+                    other => {
+                        return Err(error(format!("unknown format {:?}", other.unwrap_or(""))))
+                    }
+                });
+            }
+            Some("element") => {
+                if tokens.len() != 3 {
+                    return Err(error("expected `element <name> <count>`".to_string()));
+                }
+                let count = tokens[2]
+                    .parse()
+                    .map_err(|_| error(format!("bad element count `{}`", tokens[2])))?;
+                elements.push(Element {
+                    name: tokens[1].to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            Some("property") => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("`property` before any `element`".to_string()))?;
+                let scalar = |name: &str| {
+                    ScalarType::parse(name)
+                        .ok_or_else(|| error(format!("unknown property type `{}`", name)))
+                };
+                let property = match tokens.as_slice() {
+                    ["property", "list", count, item, name] => Property {
+                        name: name.to_string(),
+                        list: Some(scalar(count)?),
+                        scalar: scalar(item)?,
+                    },
+                    ["property", ty, name] => Property {
+                        name: name.to_string(),
+                        list: None,
+                        scalar: scalar(ty)?,
+                    },
+                    _ => return Err(error("malformed `property` line".to_string())),
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {}
+            Some(other) => return Err(error(format!("unexpected `{}`", other))),
+        }
+    }
+
+    Ok(Header {
+        format: format.ok_or("header has no `format` line")?,
+        elements,
+        body: offset,
+    })
+}
+
+/// Reads scalars from the body in whichever encoding the header declared.
+struct BodyReader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> BodyReader<'a> {
+    fn new(format: Format, bytes: &'a [u8]) -> Result<Self, String> {
+        let text = if format == Format::Ascii {
+            std::str::from_utf8(bytes).map_err(|_| "ASCII body is not valid text".to_string())?
+        } else {
+            ""
+        };
+        Ok(Self {
+            format,
+            bytes,
+            offset: 0,
+            tokens: text.split_ascii_whitespace(),
+        })
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.next().ok_or("unexpected end of data")?;
+            return token
+                .parse::<f64>()
+                .map_err(|_| format!("`{}` is not a number", token));
+        }
+
+        let size = ty.size();
+        let raw = self
+            .bytes
+            .get(self.offset..self.offset + size)
+            .ok_or("unexpected end of data")?;
+        self.offset += size;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(raw);
+        if self.format == Format::BinaryBigEndian {
+            buf[..size].reverse();
+        }
+        Ok(match ty {
+            ScalarType::I8 => buf[0] as i8 as f64,
+            ScalarType::U8 => buf[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+fn find(properties: &[Property], names: &[&str]) -> Option<usize> {
+    properties
+        .iter()
+        .position(|p| names.contains(&p.name.as_str()))
+}
+
+fn find_all<const N: usize>(properties: &[Property], names: [&[&str]; N]) -> Option<[usize; N]> {
+    let mut found = [0; N];
+    for (slot, candidates) in found.iter_mut().zip(names) {
+        *slot = find(properties, candidates)?;
+    }
+    Some(found)
+}
+
+/// Parses a whole PLY file into shared-vertex mesh data.
+pub fn parse_ply(bytes: &[u8]) -> Result<MeshData, String> {
+    let header = parse_header(bytes)?;
+    let mut reader = BodyReader::new(header.format, &bytes[header.body..])?;
+    let mut data = MeshData::default();
+    let vertex_count = header
+        .elements
+        .iter()
+        .find(|element| element.name == "vertex")
+        .map_or(0, |element| element.count);
+
+    for element in &header.elements {
+        let props = &element.properties;
+        let position = find_all(props, [&["x"], &["y"], &["z"]]);
+        let normal = find_all(props, [&["nx"], &["ny"], &["nz"]]);
+        let uv = find_all(
+            props,
+            [
+                &["u", "s", "texture_u", "texture_s"],
+                &["v", "t", "texture_v", "texture_t"],
+            ],
+        );
+        let color = find_all(
+            props,
+            [
+                &["red", "diffuse_red"],
+                &["green", "diffuse_green"],
+                &["blue", "diffuse_blue"],
+            ],
+        );
+        let indices = find(props, &["vertex_indices", "vertex_index"]);
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        if is_vertex && position.is_none() {
+            return Err("`vertex` element has no x, y, z properties".to_string());
+        }
+        if is_face && indices.is_none() {
+            return Err("`face` element has no vertex_indices property".to_string());
+        }
+
+        let mut values = vec![0.0; props.len()];
+        let mut face = Vec::new();
+        for item in 0..element.count {
+            let error = |e: String| format!("{} {}: {}", element.name, item, e);
+            for (i, property) in props.iter().enumerate() {
+                match property.list {
+                    Some(count_type) => {
+                        let count = reader.read(count_type).map_err(error)? as usize;
+                        let keep = is_face && Some(i) == indices;
+                        if keep {
+                            face.clear();
+                        }
+                        for _ in 0..count {
+                            let value = reader.read(property.scalar).map_err(error)?;
+                            if keep {
+                                face.push(value);
+                            }
+                        }
+                    }
+                    None => values[i] = reader.read(property.scalar).map_err(error)?,
+                }
+            }
+
+            if is_vertex {
+                let [x, y, z] = position.unwrap();
+                data.positions
+                    .push(Point3::new(values[x], values[y], values[z]));
+                if let Some([x, y, z]) = normal {
+                    data.normals
+                        .get_or_insert_with(Vec::new)
+                        .push(Vec3::new(values[x], values[y], values[z]));
+                }
+                if let Some([u, v]) = uv {
+                    data.uvs
+                        .get_or_insert_with(Vec::new)
+                        .push((values[u], values[v]));
+                }
+                if let Some([r, g, b]) = color {
+                    data.colors.get_or_insert_with(Vec::new).push(Color1::new(
+                        values[r] * props[r].scalar.color_scale(),
+                        values[g] * props[g].scalar.color_scale(),
+                        values[b] * props[b].scalar.color_scale(),
+                    ));
+                }
+            } else if is_face {
+                if face.len() < 3 {
+                    return Err(error(format!(
+                        "a face needs at least 3 vertices, got {}",
+                        face.len()
+                    )));
+                }
+                if let Some(index) = face.iter().find(|&&index| {
+                    index < 0.0 || index.fract() != 0.0 || index >= vertex_count as f64
+                }) {
+                    return Err(error(format!(
+                        "vertex index {} is out of range, {} vertices are defined",
+                        index, vertex_count
+                    )));
+                }
+                for pair in face[1..].windows(2) {
+                    data.indices
+                        .push([face[0] as u32, pair[0] as u32, pair[1] as u32]);
+                }
+            }
+        }
+    }
+
+    data.validate()?;
+    Ok(data)
+}
+
+pub fn load_ply(path: &Path) -> Result<MeshData, PlyError> {
+    let error = |message: String| PlyError {
+        path: path.to_path_buf(),
+        message,
+    };
+    let bytes = std::fs::read(path).map_err(|e| error(e.to_string()))?;
+    parse_ply(&bytes).map_err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                          property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                          element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    #[test]
+    fn test_ascii() {
+        let text = format!(
+            "ply\nformat ascii 1.0\ncomment made by hand\n{}\
+             0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n",
+            HEADER
+        );
+        let data = parse_ply(text.as_bytes()).unwrap();
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(data.colors.unwrap()[1], Color1::new(0.0, 1.0, 0.0));
+        assert!(data.normals.is_none());
+    }
+
+    #[test]
+    fn test_binary() {
+        let positions = [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        for (format, big_endian) in [("binary_little_endian", false), ("binary_big_endian", true)] {
+            let mut bytes = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+            for p in positions {
+                for c in p {
+                    if big_endian {
+                        bytes.extend(c.to_be_bytes());
+                    } else {
+                        bytes.extend(c.to_le_bytes());
+                    }
+                }
+                bytes.extend([255u8, 128, 0]);
+            }
+            bytes.push(4);
+            for i in [0i32, 1, 2, 3] {
+                if big_endian {
+                    bytes.extend(i.to_be_bytes());
+                } else {
+                    bytes.extend(i.to_le_bytes());
+                }
+            }
+            let data = parse_ply(&bytes).unwrap();
+            assert_eq!(data.positions[2], Point3::new(1.0, 1.0, 0.0));
+            assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+            assert_eq!(data.colors.unwrap()[0].x, 1.0);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0\n")
+            .err()
+            .unwrap();
+        assert_eq!(err, "vertex 0: unexpected end of data");
+
+        let err = parse_ply(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n")
+            .err()
+            .unwrap();
+        assert_eq!(err, "header line 3: `property` before any `element`");
+
+        for (index, shown) in [
+            ("-1", "-1"),
+            ("1.5", "1.5"),
+            ("4", "4"),
+            ("4294967296", "4294967296"),
+        ] {
+            let text = format!(
+                "ply\nformat ascii 1.0\n{}0 0 0 0 0 0\n1 0 0 0 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n\
+                 4 0 1 2 {}\n",
+                HEADER, index
+            );
+            let err = parse_ply(text.as_bytes()).err().unwrap();
+            assert_eq!(
+                err,
+                format!(
+                    "face 0: vertex index {} is out of range, 4 vertices are defined",
+                    shown
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_color_scale() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                    property float z\nproperty ushort red\nproperty uint green\nproperty char blue\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0 65535 4294967295 127\n1 0 0 0 0 0\n0 1 0 32768 0 0\n3 0 1 2\n";
+        let colors = parse_ply(text.as_bytes()).unwrap().colors.unwrap();
+        assert_eq!(colors[0], Color1::new(1.0, 1.0, 1.0));
+        assert!((colors[2].x - 0.5).abs() < 1e-4);
+    }
+}
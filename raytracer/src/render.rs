@@ -0,0 +1,304 @@
+use crate::camera::Camera;
+use crate::film::{Film, PixelSamples};
+use crate::integrator::PathTracer;
+use crate::rtweekend::seed_sample;
+use crate::sampler::{self, Sampler};
+use crate::tiles::{Tile, TileQueue};
+use indicatif::ProgressBar;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Everything needed to add samples to a film: what to trace, the camera, and how the
+/// work is split between threads.
+pub struct Renderer {
+    pub tracer: PathTracer,
+    pub camera: Camera,
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<Tile>,
+    pub threads: usize,
+    pub sampler: Arc<dyn Sampler + Send + Sync>,
+    /// Every sample draws from its own random stream derived from this, the pixel and the
+    /// number of samples the pixel had before, so the image does not depend on the threads.
+    pub seed: u64,
+}
+
+/// How a render splits its samples into passes, and whether it shows the image in between.
+#[derive(Copy, Clone, Debug)]
+pub struct RenderPlan {
+    pub samples_per_pixel: u32,
+    /// Samples of the first pass; adaptive renders take `min_spp` there.
+    pub first_pass_spp: u32,
+    pub pass_spp: u32,
+    /// Relative error below which adaptive renders stop sampling a pixel.
+    pub adaptive: Option<f64>,
+    /// Take snapshots between passes, at most once every `snapshot_interval` seconds.
+    pub snapshots: bool,
+    pub snapshot_interval: Option<f64>,
+}
+
+impl Renderer {
+    /// Renders passes until every pixel has `plan.samples_per_pixel` samples, or until no
+    /// pixel is noisy for adaptive plans. Snapshots are handed to `snapshot` with the number
+    /// of samples per pixel taken so far. Returns how long each thread worked.
+    pub fn render(
+        &self,
+        film: &Mutex<Film>,
+        plan: &RenderPlan,
+        bar: &ProgressBar,
+        mut snapshot: impl FnMut(&Film, u32),
+    ) -> Vec<Duration> {
+        let mut timers = vec![Duration::default(); self.threads];
+        let mut last_snapshot = Instant::now();
+        let mut samples_done = 0;
+        let mut active: Option<Vec<bool>> = None;
+        let mut pass = 0;
+        while samples_done < plan.samples_per_pixel {
+            let spp = if pass == 0 {
+                plan.first_pass_spp
+            } else {
+                plan.pass_spp
+            };
+            let spp = spp.min(plan.samples_per_pixel - samples_done);
+            let pass_timers = self.render_pass(film, spp, active.as_deref(), bar);
+            for (timer, pass_timer) in timers.iter_mut().zip(pass_timers) {
+                *timer += pass_timer;
+            }
+            samples_done += spp;
+            pass += 1;
+
+            if let Some(threshold) = plan.adaptive {
+                let noisy = film.lock().unwrap().noisy_pixels(threshold);
+                if !noisy.contains(&true) {
+                    break;
+                }
+                active = Some(noisy);
+            }
+
+            let snapshot_due = plan
+                .snapshot_interval
+                .is_none_or(|interval| last_snapshot.elapsed().as_secs_f64() >= interval);
+            if plan.snapshots && samples_done < plan.samples_per_pixel && snapshot_due {
+                snapshot(&film.lock().unwrap(), samples_done);
+                last_snapshot = Instant::now();
+            }
+        }
+        timers
+    }
+
+    /// Adds `spp` samples to every pixel of `film` for which `active` is true, or to all of
+    /// them without a mask, with `threads` threads taking tiles from a queue. Returns how long
+    /// each thread worked.
+    pub fn render_pass(
+        &self,
+        film: &Mutex<Film>,
+        spp: u32,
+        active: Option<&[bool]>,
+        bar: &ProgressBar,
+    ) -> Vec<Duration> {
+        let tiles = TileQueue::new(self.tiles.clone());
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..self.threads)
+                .map(|_| {
+                    let tiles = &tiles;
+                    s.spawn(move || {
+                        sampler::set_sampler(Some(self.sampler.clone()));
+                        let start_time = Instant::now();
+                        let mut samples = Vec::new();
+                        let mut taken_before = Vec::new();
+                        while let Some(tile) = tiles.next() {
+                            taken_before.clear();
+                            {
+                                let film = film.lock().unwrap();
+                                for y in tile.y0..tile.y1 {
+                                    taken_before
+                                        .extend((tile.x0..tile.x1).map(|x| film.samples(x, y)));
+                                }
+                            }
+                            // Render the whole tile before taking the film lock once for all of it.
+                            samples.clear();
+                            for y in tile.y0..tile.y1 {
+                                for x in tile.x0..tile.x1 {
+                                    if active.is_none_or(|active| active[y * self.width + x]) {
+                                        let first = taken_before[samples.len()];
+                                        samples.push(self.sample_pixel(x, y, first, spp));
+                                    } else {
+                                        samples.push(PixelSamples::new());
+                                    }
+                                }
+                            }
+                            let taken: u32 = samples.iter().map(|pixel| pixel.count).sum();
+                            film.lock().unwrap().add_tile(&tile, &samples);
+                            bar.inc(taken as u64);
+                        }
+                        start_time.elapsed()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    /// Samples `first..first + spp` of pixel `(x, y)`, counted in rows from the top.
+    fn sample_pixel(&self, x: usize, y: usize, first: u32, spp: u32) -> PixelSamples {
+        let j = self.height - y - 1;
+        let mut pixel = PixelSamples::new();
+        for s in first..first + spp {
+            let pixel_index = (y * self.width + x) as u64;
+            seed_sample(self.seed, pixel_index, s as u64);
+            sampler::start_sample(pixel_index, s);
+            let (dx, dy) = sampler::next_2d();
+            let u = (x as f64 + dx) / (self.width - 1) as f64;
+            let v = (j as f64 + dy) / (self.height - 1) as f64;
+            let r = self.camera.get_ray(u, v);
+            pixel.add(self.tracer.ray_color(&r));
+        }
+        pixel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BvhOptions;
+    use crate::sampler::SamplerKind;
+    use crate::scene::{build_scene, Scene};
+    use crate::scene_file::parse_json;
+    use crate::tiles::{make_tiles, TileOrder};
+    use crate::vec3::Color1;
+    use std::path::Path;
+
+    /// A renderer of `scene` at 16x16 with the Sobol sampler and seed 42.
+    fn renderer(scene: Scene, threads: usize, tile_size: usize) -> Renderer {
+        Renderer {
+            tracer: PathTracer {
+                world: Arc::new(scene.world),
+                lights: Arc::new(scene.lights),
+                background: scene.background,
+                rr_depth: 5,
+                max_depth: None,
+            },
+            camera: scene.camera.build(1.0),
+            width: 16,
+            height: 16,
+            tiles: make_tiles(16, 16, tile_size, TileOrder::Spiral),
+            threads,
+            sampler: SamplerKind::Sobol.build(8, 42),
+            seed: 42,
+        }
+    }
+
+    /// Renders `scene` with `renderer` in passes of the given numbers of samples.
+    fn render(scene: Scene, threads: usize, tile_size: usize, passes: &[u32]) -> Vec<Color1> {
+        let renderer = renderer(scene, threads, tile_size);
+        let film = Mutex::new(Film::new(16, 16));
+        for &spp in passes {
+            renderer.render_pass(&film, spp, None, &ProgressBar::hidden());
+        }
+        let film = film.into_inner().unwrap();
+        film.pixels().collect()
+    }
+
+    #[test]
+    fn test_render_is_reproducible() {
+        let render = |threads: usize, tile_size: usize, passes: &[u32]| {
+            render(
+                build_scene("cornell_box", BvhOptions::default()).unwrap(),
+                threads,
+                tile_size,
+                passes,
+            )
+        };
+        let serial = render(1, 16, &[4]);
+        assert_eq!(serial, render(3, 4, &[4]));
+        // Samples in later passes continue the streams of each pixel; only the rounding of
+        // the f32 sums differs.
+        let progressive = render(2, 8, &[1, 3]);
+        for (a, b) in serial.iter().zip(&progressive) {
+            assert!((*a - *b).length() < 1e-5 * (1.0 + a.length()));
+        }
+        assert_ne!(serial, render(1, 16, &[2, 2, 2]));
+    }
+
+    #[test]
+    fn test_mix_render_is_reproducible() {
+        // Two builds of the scene live at different addresses, which must not matter.
+        let desc = parse_json(
+            r#"{ "background": [0.7, 0.8, 1.0],
+                 "camera": { "lookfrom": [0, 0, 4], "lookat": [0, 0, 0], "vfov": 40 },
+                 "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": {
+                     "type": "mix", "mask": 0.5,
+                     "first": { "type": "lambertian", "albedo": [0.8, 0.2, 0.2] },
+                     "second": { "type": "mix", "mask": { "type": "checker", "even": 0.2, "odd": 0.7 },
+                                 "first": { "type": "metal", "preset": "gold", "roughness": 0.3 },
+                                 "second": { "type": "lambertian", "albedo": [0.2, 0.2, 0.8] } } } } ] }"#,
+        )
+        .unwrap();
+        let (first, second) = (
+            desc.build(Path::new(""), BvhOptions::default()).unwrap(),
+            desc.build(Path::new(""), BvhOptions::default()).unwrap(),
+        );
+        assert_eq!(render(first, 2, 4, &[4]), render(second, 3, 8, &[4]));
+    }
+
+    fn progressive(spp: u32, pass_spp: u32, snapshot_interval: Option<f64>) -> RenderPlan {
+        RenderPlan {
+            samples_per_pixel: spp,
+            first_pass_spp: pass_spp,
+            pass_spp,
+            adaptive: None,
+            snapshots: true,
+            snapshot_interval,
+        }
+    }
+
+    #[test]
+    fn test_progressive_passes() {
+        let scene = build_scene("cornell_box", BvhOptions::default()).unwrap();
+        let renderer = renderer(scene, 2, 8);
+        let film = Mutex::new(Film::new(16, 16));
+        let mut snapshots = Vec::new();
+        renderer.render(
+            &film,
+            &progressive(12, 3, None),
+            &ProgressBar::hidden(),
+            |film, samples_done| {
+                assert!((0..16).all(|y| (0..16).all(|x| film.samples(x, y) == samples_done)));
+                snapshots.push(samples_done);
+            },
+        );
+        // Four passes of 3 samples, with a snapshot after all but the last.
+        assert_eq!(snapshots, [3, 6, 9]);
+        let film = film.into_inner().unwrap();
+        assert!((0..16).all(|y| (0..16).all(|x| film.samples(x, y) == 12)));
+    }
+
+    #[test]
+    fn test_snapshot_interval() {
+        let scene = build_scene("cornell_box", BvhOptions::default()).unwrap();
+        let renderer = renderer(scene, 1, 16);
+
+        let interval = 0.02;
+        let start = Instant::now();
+        let mut times = vec![start];
+        renderer.render(
+            &Mutex::new(Film::new(16, 16)),
+            &progressive(40, 1, Some(interval)),
+            &ProgressBar::hidden(),
+            |_, _| times.push(Instant::now()),
+        );
+        for pair in times.windows(2) {
+            assert!((pair[1] - pair[0]).as_secs_f64() >= interval);
+        }
+
+        let mut count = 0;
+        renderer.render(
+            &Mutex::new(Film::new(16, 16)),
+            &progressive(8, 1, Some(3600.0)),
+            &ProgressBar::hidden(),
+            |_, _| count += 1,
+        );
+        assert_eq!(count, 0);
+    }
+}
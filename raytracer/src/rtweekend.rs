@@ -1,13 +1,75 @@
 use rand::Rng;
+use std::cell::RefCell;
 use std::f64::consts::PI;
 
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::new(rand::thread_rng().gen(), 0));
+}
+
+/// The PCG32 generator (XSH RR variant) of O'Neill. Generators with the same seed but
+/// different streams give independent sequences, which lets every sample of every pixel
+/// have its own.
+#[derive(Clone, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform in `[0, 1)`, with 53 random bits.
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 32) | self.next_u32() as u64;
+        (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+/// Mixes the bits of `x` (the SplitMix64 finalizer), so that nearby seeds give unrelated
+/// generators.
+pub fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+/// Reseeds the random number generator of the calling thread.
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(seed, 0));
+}
+
+/// Reseeds the generator of the calling thread for sample `sample` of pixel `pixel`, so
+/// that what the sample draws depends on these and `seed` only, not on which thread takes
+/// the pixel or what it rendered before.
+pub fn seed_sample(seed: u64, pixel: u64, sample: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(mix(seed ^ mix(pixel)), sample));
+}
+
 pub fn random_f64() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen::<f64>()
+    RNG.with(|rng| rng.borrow_mut().next_f64())
 }
 pub fn random_f64_1(min: f64, max: f64) -> f64 {
     min + (max - min) * random_f64()
@@ -26,3 +88,30 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
 pub fn random_i32(min: i32, max: i32) -> i32 {
     random_f64_1(min as f64, max as f64 + 1.0) as i32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcg32() {
+        // The first outputs of the reference implementation's demo, seed 42 and stream 54.
+        let mut rng = Pcg32::new(42, 54);
+        let expected = [
+            0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+        ];
+        for value in expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+
+        seed_sample(7, 100, 3);
+        let a: Vec<f64> = (0..4).map(|_| random_f64()).collect();
+        seed_sample(7, 101, 3);
+        let b: Vec<f64> = (0..4).map(|_| random_f64()).collect();
+        seed_sample(7, 100, 3);
+        let c: Vec<f64> = (0..4).map(|_| random_f64()).collect();
+        assert_eq!(a, c);
+        assert_ne!(a, b);
+        assert!(a.iter().all(|x| (0.0..1.0).contains(x)));
+    }
+}
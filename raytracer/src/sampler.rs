@@ -0,0 +1,482 @@
+//! Samplers decide the random numbers a pixel sample draws for its camera ray and for each
+//! bounce, spreading the samples of a pixel more evenly than independent random numbers.
+//!
+//! Every draw takes the next dimension of the current sample; a 2D draw counts as one
+//! dimension. The camera always uses the first `CAMERA_DIMENSIONS`, and bounce `depth`
+//! starts at a fixed dimension of its own, so that each dimension keeps its meaning from one
+//! sample to the next. Draws past the dimensions of the camera or the current bounce, like
+//! the picks of deeply nested mix materials, come from the random numbers of the sample
+//! instead of taking the dimensions of the next bounce.
+
+use crate::rtweekend::{mix, random_f64};
+use clap::ValueEnum;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// Dimensions of the camera ray: pixel position, lens position and time.
+const CAMERA_DIMENSIONS: u32 = 3;
+/// Dimensions reserved for each bounce: the light sample and the scattered direction, with
+/// room for a material or lobe choice and a mix pick.
+const BOUNCE_DIMENSIONS: u32 = 4;
+
+/// Numbers in `[0, 1)` for dimension `dimension` of sample `index` of pixel `pixel`.
+pub trait Sampler {
+    fn get_1d(&self, pixel: u64, index: u32, dimension: u32) -> f64;
+    fn get_2d(&self, pixel: u64, index: u32, dimension: u32) -> (f64, f64);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// One jittered sample per stratum, strata shuffled per pixel and dimension
+    Stratified,
+    /// The Halton sequence, Owen-scrambled per pixel and dimension
+    Halton,
+    /// The Sobol sequence with hash-based Owen scrambling
+    Sobol,
+}
+
+impl SamplerKind {
+    /// A sampler for `samples_per_pixel` samples whose scrambling is derived from `seed`.
+    pub fn build(&self, samples_per_pixel: u32, seed: u64) -> Arc<dyn Sampler + Send + Sync> {
+        match self {
+            SamplerKind::Independent => Arc::new(Independent),
+            SamplerKind::Stratified => Arc::new(Stratified::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Arc::new(Halton::new(seed)),
+            SamplerKind::Sobol => Arc::new(Sobol::new(seed)),
+        }
+    }
+}
+
+struct SampleState {
+    sampler: Option<Arc<dyn Sampler + Send + Sync>>,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+    /// The first dimension past those of the camera or the current bounce.
+    end: u32,
+}
+
+thread_local! {
+    static STATE: RefCell<SampleState> = const {
+        RefCell::new(SampleState {
+            sampler: None,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            end: 0,
+        })
+    };
+}
+
+/// Sets the sampler of the calling thread; without one, draws are independent.
+pub fn set_sampler(sampler: Option<Arc<dyn Sampler + Send + Sync>>) {
+    STATE.with(|state| state.borrow_mut().sampler = sampler);
+}
+
+/// Starts sample `index` of pixel `pixel` at its first dimension.
+pub fn start_sample(pixel: u64, index: u32) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.pixel = pixel;
+        state.index = index;
+        state.dimension = 0;
+        state.end = CAMERA_DIMENSIONS;
+    });
+}
+
+/// Moves to the dimensions of bounce `depth` of the current sample.
+pub fn start_bounce(depth: i32) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.dimension = CAMERA_DIMENSIONS + depth as u32 * BOUNCE_DIMENSIONS;
+        state.end = state.dimension + BOUNCE_DIMENSIONS;
+    });
+}
+
+pub fn next_1d() -> f64 {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let dimension = state.dimension;
+        state.dimension += 1;
+        match &state.sampler {
+            Some(sampler) if dimension < state.end => {
+                sampler.get_1d(state.pixel, state.index, dimension)
+            }
+            _ => random_f64(),
+        }
+    })
+}
+
+pub fn next_2d() -> (f64, f64) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let dimension = state.dimension;
+        state.dimension += 1;
+        match &state.sampler {
+            Some(sampler) if dimension < state.end => {
+                sampler.get_2d(state.pixel, state.index, dimension)
+            }
+            _ => (random_f64(), random_f64()),
+        }
+    })
+}
+
+/// A 32-bit hash of `pixel` and `dimension` under `seed`, to scramble them independently.
+fn hash(seed: u64, pixel: u64, dimension: u32) -> u32 {
+    (mix(seed ^ mix(pixel ^ mix(dimension as u64))) >> 32) as u32
+}
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 * (1.0 / 4294967296.0)
+}
+
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn get_1d(&self, _pixel: u64, _index: u32, _dimension: u32) -> f64 {
+        random_f64()
+    }
+
+    fn get_2d(&self, _pixel: u64, _index: u32, _dimension: u32) -> (f64, f64) {
+        (random_f64(), random_f64())
+    }
+}
+
+/// Splits each dimension into one stratum per sample, a grid for 2D dimensions, and gives
+/// sample `index` a random point in the stratum a per-pixel shuffle assigns it.
+pub struct Stratified {
+    samples: u32,
+    seed: u64,
+}
+
+impl Stratified {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples: samples_per_pixel.max(1),
+            seed,
+        }
+    }
+}
+
+impl Sampler for Stratified {
+    fn get_1d(&self, pixel: u64, index: u32, dimension: u32) -> f64 {
+        let n = self.samples;
+        let stratum = permute(index % n, n, hash(self.seed, pixel, dimension));
+        (stratum as f64 + random_f64()) / n as f64
+    }
+
+    fn get_2d(&self, pixel: u64, index: u32, dimension: u32) -> (f64, f64) {
+        let nx = (self.samples as f64).sqrt().ceil() as u32;
+        let ny = self.samples.div_ceil(nx);
+        let stratum = permute(
+            index % (nx * ny),
+            nx * ny,
+            hash(self.seed, pixel, dimension),
+        );
+        (
+            ((stratum % nx) as f64 + random_f64()) / nx as f64,
+            ((stratum / nx) as f64 + random_f64()) / ny as f64,
+        )
+    }
+}
+
+/// A pseudo-random permutation of `0..n` indexed by `i`, from Kensler's "Correlated
+/// Multi-Jittered Sampling".
+fn permute(mut i: u32, n: u32, p: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        // Cycle walking: values past n are mapped again until they land inside.
+        if i < n {
+            return (i.wrapping_add(p)) % n;
+        }
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Radical inverses of the sample index in a prime base per dimension, with the digits
+/// Owen-scrambled per pixel and dimension: each digit is permuted by a hash of the digits
+/// before it. Dimensions past the prime table are independent.
+pub struct Halton {
+    seed: u64,
+}
+
+impl Halton {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn component(&self, pixel: u64, index: u32, dimension: u32) -> f64 {
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                owen_scrambled_radical_inverse(base, index, hash(self.seed, pixel, dimension))
+            }
+            None => random_f64(),
+        }
+    }
+}
+
+impl Sampler for Halton {
+    fn get_1d(&self, pixel: u64, index: u32, dimension: u32) -> f64 {
+        self.component(pixel, index, 2 * dimension)
+    }
+
+    fn get_2d(&self, pixel: u64, index: u32, dimension: u32) -> (f64, f64) {
+        (
+            self.component(pixel, index, 2 * dimension),
+            self.component(pixel, index, 2 * dimension + 1),
+        )
+    }
+}
+
+/// `index` with its digits in `base` mirrored around the decimal point, each permuted by a
+/// hash of `seed` and the digits below it. Digits past the last nonzero one are scrambled
+/// too, until they fall below the precision of an `f64`.
+fn owen_scrambled_radical_inverse(base: u32, mut index: u32, seed: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    while 1.0 - inv_base_n < 1.0 {
+        let digit = index % base;
+        index /= base;
+        let digit_seed = mix(seed as u64 ^ reversed) as u32;
+        reversed = reversed * base as u64 + permute(digit, base, digit_seed) as u64;
+        inv_base_n *= inv_base;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON / 2.0)
+}
+
+/// The first two dimensions of the Sobol sequence, Owen-scrambled with the hash of Burley's
+/// "Practical Hash-based Owen Scrambling". Higher dimensions reuse them on an index that is
+/// shuffled per pixel and dimension, which keeps every power-of-two block of samples
+/// stratified in each 2D dimension.
+pub struct Sobol {
+    seed: u64,
+}
+
+impl Sobol {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Point `index` of the sequence in dimension 0 or 1, before scrambling.
+    fn sobol(index: u32, dimension: u32) -> u32 {
+        if dimension == 0 {
+            return index.reverse_bits();
+        }
+        let mut result = 0;
+        let mut v = 1u32 << 31;
+        let mut index = index;
+        while index > 0 {
+            if index & 1 == 1 {
+                result ^= v;
+            }
+            index >>= 1;
+            v ^= v >> 1;
+        }
+        result
+    }
+}
+
+impl Sampler for Sobol {
+    fn get_1d(&self, pixel: u64, index: u32, dimension: u32) -> f64 {
+        let seed = hash(self.seed, pixel, dimension);
+        let index = nested_uniform_scramble(index, seed);
+        to_unit(nested_uniform_scramble(
+            Self::sobol(index, 0),
+            mix(seed as u64 + 1) as u32,
+        ))
+    }
+
+    fn get_2d(&self, pixel: u64, index: u32, dimension: u32) -> (f64, f64) {
+        let seed = hash(self.seed, pixel, dimension);
+        let index = nested_uniform_scramble(index, seed);
+        (
+            to_unit(nested_uniform_scramble(
+                Self::sobol(index, 0),
+                mix(seed as u64 + 1) as u32,
+            )),
+            to_unit(nested_uniform_scramble(
+                Self::sobol(index, 1),
+                mix(seed as u64 + 2) as u32,
+            )),
+        )
+    }
+}
+
+/// Laine and Karras' hash, in which every bit only depends on the bits below it.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// An Owen scramble of the bits of `x`, most significant first.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::material::{Lambertian, Material, MixMaterial, ScatterRecord};
+    use crate::rtweekend::seed_sample;
+    use crate::texture::{SolidColor, Texture};
+    use crate::vec3::{Color1, Vec3};
+    use crate::Ray;
+    use std::sync::Mutex;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn test_samplers_stratify() {
+        // 64 samples of the stratified and Sobol samplers put one point in each cell of an
+        // 8 by 8 grid, in every pixel and dimension.
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let sampler = kind.build(64, 3);
+            for pixel in 0..20 {
+                for dimension in [0, 1, 7, 30] {
+                    let mut cells = [0; 64];
+                    for index in 0..64 {
+                        seed_sample(3, pixel, index as u64);
+                        let (x, y) = sampler.get_2d(pixel, index, dimension);
+                        assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                        cells[(y * 8.0) as usize * 8 + (x * 8.0) as usize] += 1;
+                    }
+                    assert!(cells.iter().all(|&c| c == 1), "{:?}", kind);
+                }
+            }
+        }
+        for i in 0..100 {
+            let p = permute(i, 100, 12345);
+            assert!((0..100).filter(|&j| permute(j, 100, 12345) == p).count() == 1);
+        }
+    }
+
+    #[test]
+    fn test_samplers_reduce_error() {
+        // Mean squared error of estimating the integral of x * y over the unit square, 1/4,
+        // with 64 samples per pixel, in the first dimension (bases 2 and 3 for Halton).
+        let error = |kind: SamplerKind| {
+            let sampler = kind.build(64, 5);
+            let pixels = 200;
+            let total: f64 = (0..pixels)
+                .map(|pixel| {
+                    let estimate = (0..64)
+                        .map(|index| {
+                            seed_sample(5, pixel, index as u64);
+                            let (x, y) = sampler.get_2d(pixel, index, 0);
+                            x * y
+                        })
+                        .sum::<f64>()
+                        / 64.0;
+                    (estimate - 0.25).powi(2)
+                })
+                .sum();
+            total / pixels as f64
+        };
+        let independent = error(SamplerKind::Independent);
+        for kind in KINDS {
+            if kind != SamplerKind::Independent {
+                let e = error(kind);
+                assert!(
+                    e * 10.0 < independent,
+                    "{:?}: {} vs {}",
+                    kind,
+                    e,
+                    independent
+                );
+            }
+        }
+    }
+
+    /// Returns a half in every dimension and remembers which dimensions were drawn.
+    struct Recorder(Mutex<Vec<u32>>);
+
+    impl Sampler for Recorder {
+        fn get_1d(&self, _pixel: u64, _index: u32, dimension: u32) -> f64 {
+            self.0.lock().unwrap().push(dimension);
+            0.5
+        }
+
+        fn get_2d(&self, _pixel: u64, _index: u32, dimension: u32) -> (f64, f64) {
+            self.0.lock().unwrap().push(dimension);
+            (0.5, 0.5)
+        }
+    }
+
+    #[test]
+    fn test_nested_mix_stays_in_its_bounce() {
+        // Four nested mixes pick four times before the diffuse direction is drawn, one draw
+        // more than a bounce has dimensions for.
+        let mask: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(SolidColor::scalar(0.5)));
+        let mut material: Arc<dyn Material + Send + Sync> =
+            Arc::new(Lambertian::new(&Color1::new(0.5, 0.5, 0.5)));
+        for _ in 0..4 {
+            material = Arc::new(MixMaterial::new(
+                Some(material.clone()),
+                Some(material),
+                mask.clone(),
+            ));
+        }
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        set_sampler(Some(recorder.clone()));
+        seed_sample(1, 0, 0);
+        start_sample(0, 0);
+        start_bounce(1);
+        let r_in = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut srec = ScatterRecord::new();
+        assert!(material.scatter(&r_in, &mut rec, &mut srec));
+        srec.pdf_ptr.clone().unwrap().generate();
+        set_sampler(None);
+
+        let start = CAMERA_DIMENSIONS + BOUNCE_DIMENSIONS;
+        let drawn = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            drawn,
+            (start..start + BOUNCE_DIMENSIONS).collect::<Vec<_>>()
+        );
+    }
+}
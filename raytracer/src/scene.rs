@@ -0,0 +1,706 @@
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::bvh::{BvhBuilder, BvhOptions, BvhStats};
+use crate::camera::Camera;
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::{Hittable, RotateY, Translate};
+use crate::hittable_list::HittableList;
+use crate::instance::Instance;
+use crate::material::{Conductor, Dielectric, DiffuseLight, Lambertian, Material, MetalPreset};
+use crate::moving_sphere::MovingSphere;
+use crate::r#box::Box1;
+use crate::rtweekend::{random_f64, random_f64_1, random_i32};
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, ImageTecture, NoiseTexture, Texture};
+use crate::transform::Transform;
+use crate::vec3::{Color1, Point3, Vec3};
+use std::sync::Arc;
+
+/// Names and short descriptions of the built-in scenes, in the order of the old `match` arms.
+pub const SCENES: [(&str, &str); 9] = [
+    (
+        "random_scene",
+        "Book 1 final scene: random spheres on a checkered ground",
+    ),
+    ("two_spheres", "Two checkered spheres"),
+    (
+        "two_perlin_spheres",
+        "Two spheres with Perlin noise texture",
+    ),
+    ("earth", "Earth-mapped sphere"),
+    (
+        "simple_light",
+        "Perlin spheres lit by a rectangle and a sphere light",
+    ),
+    ("cornell_box", "Cornell box with two rotated boxes"),
+    ("cornell_smoke", "Cornell box with smoke and fog blocks"),
+    ("final_scene", "Book 2 final scene"),
+    (
+        "instances",
+        "400 instances of one shared cluster of spheres",
+    ),
+];
+
+/// Camera placement of a scene, turned into a `Camera` once the image aspect ratio is known.
+#[derive(Copy, Clone)]
+pub struct CameraSetup {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl Default for CameraSetup {
+    fn default() -> Self {
+        Self {
+            lookfrom: Point3::new(13.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+}
+
+impl CameraSetup {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            &self.lookfrom,
+            &self.lookat,
+            &self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            (self.focus_dist, self.time0, self.time1),
+        )
+    }
+}
+
+/// Everything needed to render a scene; the image settings are defaults the command line may override.
+pub struct Scene {
+    pub world: HittableList,
+    /// Emitters to sample directly; they need `pdf_value` and `random`, and their material is unused.
+    pub lights: HittableList,
+    pub background: Color1,
+    pub camera: CameraSetup,
+    pub aspect_ratio: f64,
+    pub width: usize,
+    pub samples_per_pixel: i32,
+    /// Statistics of the BVHs built for the scene.
+    pub bvh_stats: Vec<BvhStats>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            world: HittableList::new(),
+            lights: HittableList::new(),
+            background: Color1::new(0.0, 0.0, 0.0),
+            camera: CameraSetup::default(),
+            aspect_ratio: 16.0 / 9.0,
+            width: 400,
+            samples_per_pixel: 100,
+            bvh_stats: Vec::new(),
+        }
+    }
+}
+
+/// Builds the built-in scene called `name` with BVHs built as `bvh_options` says, or `None`
+/// if there is no such scene.
+pub fn build_scene(name: &str, bvh_options: BvhOptions) -> Option<Scene> {
+    let mut scene = Scene::default();
+    let mut bvh = BvhBuilder::new(bvh_options);
+    let sky = Color1::new(0.70, 0.80, 1.00);
+
+    match name {
+        "random_scene" => {
+            scene.world = random_scene(&mut bvh);
+            scene.background = sky;
+            scene.camera.aperture = 0.1;
+        }
+
+        "two_spheres" => {
+            scene.world = two_spheres();
+            scene.background = sky;
+        }
+
+        "two_perlin_spheres" => {
+            scene.world = two_perlin_spheres();
+            scene.background = sky;
+        }
+
+        "earth" => {
+            scene.world = earth();
+            scene.background = sky;
+        }
+
+        "simple_light" => {
+            scene.world = simple_light();
+            scene
+                .lights
+                .add(Some(Arc::new(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, None))));
+            scene.lights.add(Some(Arc::new(Sphere::new(
+                Point3::new(0.0, 7.0, 0.0),
+                2.0,
+                None,
+            ))));
+            scene.samples_per_pixel = 400;
+            scene.camera.lookfrom = Point3::new(26.0, 3.0, 6.0);
+            scene.camera.lookat = Point3::new(0.0, 2.0, 0.0);
+        }
+
+        "cornell_box" => {
+            scene.world = cornell_box();
+            scene.lights.add(Some(Arc::new(XzRect::new(
+                213.0, 343.0, 227.0, 332.0, 554.0, None,
+            ))));
+            scene.aspect_ratio = 1.0;
+            scene.width = 600;
+            scene.samples_per_pixel = 200;
+            scene.camera.lookfrom = Point3::new(278.0, 278.0, -800.0);
+            scene.camera.lookat = Point3::new(278.0, 278.0, 0.0);
+            scene.camera.vfov = 40.0;
+        }
+
+        "cornell_smoke" => {
+            scene.world = cornell_smoke();
+            scene.lights.add(Some(Arc::new(XzRect::new(
+                113.0, 443.0, 127.0, 432.0, 554.0, None,
+            ))));
+            scene.aspect_ratio = 1.0;
+            scene.width = 600;
+            scene.samples_per_pixel = 200;
+            scene.camera.lookfrom = Point3::new(278.0, 278.0, -800.0);
+            scene.camera.lookat = Point3::new(278.0, 278.0, 0.0);
+            scene.camera.vfov = 40.0;
+        }
+
+        "final_scene" => {
+            scene.world = final_scene(&mut bvh);
+            scene.lights.add(Some(Arc::new(XzRect::new(
+                123.0, 423.0, 147.0, 412.0, 554.0, None,
+            ))));
+            scene.aspect_ratio = 1.0;
+            scene.width = 800;
+            scene.samples_per_pixel = 50;
+            scene.camera.lookfrom = Point3::new(478.0, 278.0, -600.0);
+            scene.camera.lookat = Point3::new(278.0, 278.0, 0.0);
+            scene.camera.vfov = 40.0;
+        }
+
+        "instances" => {
+            scene.world = instances(&mut bvh);
+            scene.background = sky;
+        }
+
+        _ => return None,
+    }
+
+    scene.bvh_stats = bvh.finish();
+    Some(scene)
+}
+
+fn random_scene(bvh: &mut BvhBuilder) -> HittableList {
+    let mut world = HittableList::new();
+
+    let checker: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(CheckerTexture::new1(
+        Color1::new(0.2, 0.3, 0.1),
+        Color1::new(0.9, 0.9, 0.9),
+    )));
+    world.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(checker))),
+    ))));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = random_f64();
+            let center = Point3::new(
+                a as f64 + 0.9 * random_f64(),
+                0.2,
+                b as f64 + 0.9 * random_f64(),
+            );
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let sphere_material: Option<Arc<dyn Material + Send + Sync>>;
+
+                if choose_mat < 0.8 {
+                    let albedo = Color1::random().elemul(Color1::random());
+                    sphere_material = Some(Arc::new(Lambertian::new(&albedo)));
+                    let center2 = center + Vec3::new(0.0, random_f64_1(0.0, 0.5), 0.0);
+                    world.add(Some(Arc::new(MovingSphere::new(
+                        center,
+                        center2,
+                        0.0,
+                        1.0,
+                        0.2,
+                        sphere_material,
+                    ))));
+                } else if choose_mat < 0.95 {
+                    let preset = MetalPreset::ALL[random_i32(0, 3) as usize];
+                    let roughness = random_f64_1(0.0, 0.5);
+                    sphere_material = Some(Arc::new(Conductor::from_preset(preset, roughness)));
+                    world.add(Some(Arc::new(Sphere::new(center, 0.2, sphere_material))));
+                } else {
+                    sphere_material = Some(Arc::new(Dielectric::new(1.5)));
+                    world.add(Some(Arc::new(Sphere::new(center, 0.2, sphere_material))));
+                }
+            }
+        }
+    }
+    let material1: Option<Arc<dyn Material + Send + Sync>> = Some(Arc::new(Dielectric::new(1.5)));
+    world.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        material1,
+    ))));
+
+    let material2: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.4, 0.2, 0.1))));
+    world.add(Some(Arc::new(Sphere::new(
+        Point3::new(-4.0, 1.0, 0.0),
+        1.0,
+        material2,
+    ))));
+
+    let material3: Option<Arc<dyn Material + Send + Sync>> = Some(Arc::new(
+        Conductor::from_reflectance(&Color1::new(0.7, 0.6, 0.5), 0.0),
+    ));
+    world.add(Some(Arc::new(Sphere::new(
+        Point3::new(4.0, 1.0, 0.0),
+        1.0,
+        material3,
+    ))));
+
+    let mut objects = HittableList::new();
+    objects.add(Some(bvh.build(&world, 0.0, 1.0)));
+    objects
+}
+
+fn two_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+    let checker: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(CheckerTexture::new1(
+        Color1::new(0.2, 0.3, 0.1),
+        Color1::new(0.9, 0.9, 0.9),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, -10.0, 0.0),
+        10.0,
+        Some(Arc::new(Lambertian::new1(checker.clone()))),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 10.0, 0.0),
+        10.0,
+        Some(Arc::new(Lambertian::new1(checker))),
+    ))));
+    objects
+}
+
+fn two_perlin_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(4.0)));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(pertext.clone()))),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Some(Arc::new(Lambertian::new1(pertext))),
+    ))));
+    objects
+}
+
+fn earth() -> HittableList {
+    let mut world = HittableList::new();
+    let earth_texture: Option<Arc<dyn Texture + Send + Sync>> =
+        Some(Arc::new(ImageTecture::new("earthmap.jpg")));
+    let earth_surface: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new1(earth_texture)));
+    let globe: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        2.0,
+        earth_surface,
+    )));
+    world.add(globe);
+
+    world
+}
+
+fn simple_light() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(4.0)));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(pertext.clone()))),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Some(Arc::new(Lambertian::new1(pertext))),
+    ))));
+
+    let difflight: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(DiffuseLight::new1(Color1::new(4.0, 4.0, 4.0))));
+    objects.add(Some(Arc::new(XyRect::new(
+        3.0,
+        5.0,
+        1.0,
+        3.0,
+        -2.0,
+        difflight.clone(),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 7.0, 0.0),
+        2.0,
+        difflight.clone(),
+    ))));
+
+    objects
+}
+
+fn cornell_box() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let red: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.65, 0.05, 0.05))));
+    let white: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
+    let green: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.12, 0.45, 0.15))));
+    let light: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(DiffuseLight::new1(Color1::new(15.0, 15.0, 15.0))));
+
+    objects.add(Some(Arc::new(YzRect::new(
+        0.0, 555.0, 0.0, 555.0, 555.0, green,
+    ))));
+    objects.add(Some(Arc::new(YzRect::new(
+        0.0, 555.0, 0.0, 555.0, 0.0, red,
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ))));
+    objects.add(Some(Arc::new(XyRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ))));
+
+    let mut box1: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    )));
+    box1 = Some(Arc::new(RotateY::new(box1, 15.0)));
+    box1 = Some(Arc::new(Translate::new(
+        box1,
+        &Vec3::new(265.0, 0.0, 295.0),
+    )));
+    objects.add(box1);
+
+    let mut box2: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white.clone(),
+    )));
+    box2 = Some(Arc::new(RotateY::new(box2, -18.0)));
+    box2 = Some(Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0))));
+    objects.add(box2);
+
+    objects
+}
+
+fn cornell_smoke() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let red: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.65, 0.05, 0.05))));
+    let white: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
+    let green: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.12, 0.45, 0.15))));
+    let light: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(DiffuseLight::new1(Color1::new(7.0, 7.0, 7.0))));
+
+    objects.add(Some(Arc::new(YzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        green.clone(),
+    ))));
+    objects.add(Some(Arc::new(YzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        red.clone(),
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        113.0,
+        443.0,
+        127.0,
+        432.0,
+        554.0,
+        light.clone(),
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ))));
+    objects.add(Some(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    ))));
+    objects.add(Some(Arc::new(XyRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ))));
+    let mut box1: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    )));
+    box1 = Some(Arc::new(RotateY::new(box1, 15.0)));
+    box1 = Some(Arc::new(Translate::new(
+        box1,
+        &Vec3::new(265.0, 0.0, 295.0),
+    )));
+
+    let mut box2: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Box1::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white.clone(),
+    )));
+    box2 = Some(Arc::new(RotateY::new(box2, -18.0)));
+    box2 = Some(Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0))));
+
+    objects.add(Some(Arc::new(ConstantMedium::new1(
+        box1,
+        0.01,
+        Color1::new(0.0, 0.0, 0.0),
+    ))));
+    objects.add(Some(Arc::new(ConstantMedium::new1(
+        box2,
+        0.01,
+        Color1::new(1.0, 1.0, 1.0),
+    ))));
+
+    objects
+}
+
+fn final_scene(bvh: &mut BvhBuilder) -> HittableList {
+    let mut boxes1 = HittableList::new();
+    let mut objects = HittableList::new();
+    let ground: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.48, 0.83, 0.53))));
+
+    for i in 0..20 {
+        for j in 0..20 {
+            boxes1.add(Some(Arc::new(Box1::new(
+                Point3::new(-1000.0 + i as f64 * 100.0, 0.0, -1000.0 + j as f64 * 100.0),
+                Point3::new(
+                    -1000.0 + i as f64 * 100.0 + 100.0,
+                    random_f64_1(1.0, 101.0),
+                    -1000.0 + j as f64 * 100.0 + 100.0,
+                ),
+                ground.clone(),
+            ))));
+        }
+    }
+
+    objects.add(Some(bvh.build(&boxes1, 0.0, 1.0)));
+
+    let light: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(DiffuseLight::new1(Color1::new(7.0, 7.0, 7.0))));
+    objects.add(Some(Arc::new(XzRect::new(
+        123.0,
+        423.0,
+        147.0,
+        412.0,
+        554.0,
+        light.clone(),
+    ))));
+
+    let center1 = Point3::new(400.0, 400.0, 200.0);
+    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+    let moving_sphere_material: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.7, 0.3, 0.1))));
+    objects.add(Some(Arc::new(MovingSphere::new(
+        center1,
+        center2,
+        0.0,
+        1.0,
+        50.0,
+        moving_sphere_material,
+    ))));
+
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(260.0, 150.0, 45.0),
+        50.0,
+        Some(Arc::new(Dielectric::new(1.5))),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 150.0, 145.0),
+        50.0,
+        Some(Arc::new(Conductor::from_reflectance(
+            &Color1::new(0.8, 0.8, 0.9),
+            1.0,
+        ))),
+    ))));
+
+    let mut boundary: Option<Arc<dyn Hittable + Send + Sync>> = Some(Arc::new(Sphere::new(
+        Point3::new(360.0, 150.0, 145.0),
+        70.0,
+        Some(Arc::new(Dielectric::new(1.5))),
+    )));
+    objects.add(boundary.clone());
+    objects.add(Some(Arc::new(ConstantMedium::new1(
+        boundary,
+        0.2,
+        Color1::new(0.2, 0.4, 0.9),
+    ))));
+    boundary = Some(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        5000.0,
+        Some(Arc::new(Dielectric::new(1.5))),
+    )));
+    objects.add(Some(Arc::new(ConstantMedium::new1(
+        boundary,
+        0.0001,
+        Color1::new(1.0, 1.0, 1.0),
+    ))));
+
+    let emat: Option<Arc<dyn Material + Send + Sync>> = Some(Arc::new(Lambertian::new1(Some(
+        Arc::new(ImageTecture::new("earthmap.jpg")),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(400.0, 200.0, 400.0),
+        100.0,
+        emat,
+    ))));
+    let pertext: Option<Arc<dyn Texture + Send + Sync>> = Some(Arc::new(NoiseTexture::new(0.1)));
+    objects.add(Some(Arc::new(Sphere::new(
+        Point3::new(220.0, 280.0, 300.0),
+        80.0,
+        Some(Arc::new(Lambertian::new1(pertext))),
+    ))));
+
+    let mut boxes2 = HittableList::new();
+    let white: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
+    let ns = 1000;
+    for _j in 0..ns {
+        boxes2.add(Some(Arc::new(Sphere::new(
+            Point3::random1(0.0, 165.0),
+            10.0,
+            white.clone(),
+        ))));
+    }
+
+    objects.add(Some(Arc::new(Translate::new(
+        Some(Arc::new(RotateY::new(
+            Some(bvh.build(&boxes2, 0.0, 1.0)),
+            15.0,
+        ))),
+        &Vec3::new(-100.0, 270.0, 395.0),
+    ))));
+
+    objects
+}
+
+fn instances(bvh: &mut BvhBuilder) -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Some(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new(&Color1::new(0.5, 0.5, 0.5)))),
+    ))));
+
+    // One ball of small spheres, built into a BVH once and shared by every instance.
+    let white: Option<Arc<dyn Material + Send + Sync>> =
+        Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73))));
+    let mut cluster = HittableList::new();
+    for _ in 0..500 {
+        cluster.add(Some(Arc::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0) + Vec3::random_in_unit_sphere() * 0.9,
+            0.1,
+            white.clone(),
+        ))));
+    }
+    let prototype = bvh.build(&cluster, 0.0, 1.0);
+
+    let mut instances = HittableList::new();
+    for a in -10..10 {
+        for b in -10..10 {
+            let size = random_f64_1(0.25, 0.4);
+            let transform = Transform::translate(&Vec3::new(
+                a as f64 + 0.2 * random_f64(),
+                0.0,
+                b as f64 + 0.2 * random_f64(),
+            )) * Transform::rotate_y(random_f64_1(0.0, 360.0))
+                * Transform::scale(&Vec3::new(size, size, size)).unwrap();
+            // Every other instance overrides the shared white material.
+            let material: Option<Arc<dyn Material + Send + Sync>> = if (a + b) % 2 == 0 {
+                Some(Arc::new(Lambertian::new(&Vec3::elemul(
+                    Color1::random(),
+                    Color1::random(),
+                ))))
+            } else {
+                None
+            };
+            instances.add(Some(Arc::new(Instance::new(
+                Some(prototype.clone()),
+                transform,
+                material,
+            ))));
+        }
+    }
+    world.add(Some(bvh.build(&instances, 0.0, 1.0)));
+
+    world
+}
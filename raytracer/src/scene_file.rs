@@ -0,0 +1,1428 @@
+//! Declarative scene descriptions loaded from JSON or TOML.
+//!
+//! Textures and materials can be declared once in the `textures` / `materials` tables and
+//! referenced by name, or written inline where they are used. A color can stand in for any
+//! texture, and a number for a gray one. Every error carries the path of the offending key, e.g. `objects[2].material`;
+//! syntax and type errors also carry the line and column reported by the parser.
+//!
+//! Objects repeated many times can be declared once in the `prototypes` table, as a list of
+//! objects that gets its own BVH, and placed with `instance` objects that share it.
+//!
+//! Top-level spheres, rectangles and boxes with a `diffuse_light` material, also when
+//! translated, rotated, scaled or transformed, are sampled directly as lights.
+
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::bvh::{BvhBuilder, BvhOptions};
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::{Hittable, RotateX, RotateY, RotateZ, Scale, Transformed, Translate};
+use crate::hittable_list::HittableList;
+use crate::instance::Instance;
+use crate::material::{
+    BumpMap, Conductor, Dielectric, DiffuseLight, Isotropic, Lambertian, Material, MetalPreset,
+    MixMaterial, NormalMap, Principled,
+};
+use crate::mesh::{MeshData, TriangleMesh};
+use crate::moving_sphere::MovingSphere;
+use crate::obj::load_obj;
+use crate::ply::load_ply;
+use crate::r#box::Box1;
+use crate::scene::{CameraSetup, Scene};
+use crate::sphere::Sphere;
+use crate::texture::{
+    CheckerTexture, ImageTecture, NoiseTexture, SolidColor, Texture, VertexColorTexture,
+};
+use crate::transform::Transform;
+use crate::triangle::Triangle;
+use crate::vec3::{Color1, Vec3};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type TexturePtr = Option<Arc<dyn Texture + Send + Sync>>;
+type MaterialPtr = Option<Arc<dyn Material + Send + Sync>>;
+type HittablePtr = Option<Arc<dyn Hittable + Send + Sync>>;
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(PathBuf, std::io::Error),
+    UnknownFormat(PathBuf),
+    /// The file is not valid JSON / TOML or does not match the schema.
+    Parse {
+        key: String,
+        message: String,
+    },
+    /// The file parsed but describes something that cannot be built.
+    Invalid {
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            SceneFileError::UnknownFormat(path) => write!(
+                f,
+                "cannot tell the format of {}, expected a .json or .toml file",
+                path.display()
+            ),
+            SceneFileError::Parse { key, message } | SceneFileError::Invalid { key, message } => {
+                if key.is_empty() || key == "." {
+                    write!(f, "{}", message)
+                } else {
+                    write!(f, "at `{}`: {}", key, message)
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+fn invalid(key: &str, message: impl Into<String>) -> SceneFileError {
+    SceneFileError::Invalid {
+        key: key.to_string(),
+        message: message.into(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub background: [f64; 3],
+    pub width: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub samples_per_pixel: Option<i32>,
+    /// Wrap the whole world in a BVH.
+    #[serde(default)]
+    pub bvh: bool,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    pub prototypes: BTreeMap<String, Vec<ObjectDesc>>,
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    pub vup: [f64; 3],
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        let setup = CameraSetup::default();
+        Self {
+            lookfrom: to_array(setup.lookfrom),
+            lookat: to_array(setup.lookat),
+            vup: to_array(setup.vup),
+            vfov: setup.vfov,
+            aperture: setup.aperture,
+            focus_dist: setup.focus_dist,
+            time0: setup.time0,
+            time1: setup.time1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        even: TextureRef,
+        odd: TextureRef,
+    },
+    Noise {
+        scale: f64,
+    },
+    Image {
+        path: String,
+    },
+    /// The vertex colors of the mesh that was hit, white elsewhere.
+    VertexColor {},
+}
+
+/// A texture given by name, by a plain color or gray level, or inline.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Color([f64; 3]),
+    Gray(f64),
+    Named(String),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    /// A GGX conductor given by exactly one of its color at normal incidence, a preset, or
+    /// its complex index of refraction `eta` and `k`.
+    #[serde(alias = "medal")]
+    Metal {
+        albedo: Option<[f64; 3]>,
+        preset: Option<MetalPreset>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default, alias = "fuzz")]
+        roughness: f64,
+    },
+    /// Glass, frosted with a `roughness` above zero, and tinted by an `absorption`
+    /// coefficient per unit distance inside.
+    Dielectric {
+        ir: f64,
+        #[serde(default)]
+        roughness: f64,
+        #[serde(default)]
+        absorption: [f64; 3],
+    },
+    /// The Disney principled BSDF; parameters left out keep their defaults.
+    Principled(Box<PrincipledDesc>),
+    /// `first` where the mean of `mask` is 0, `second` where it is 1, and a blend between.
+    Mix {
+        first: Box<MaterialRef>,
+        second: Box<MaterialRef>,
+        mask: TextureRef,
+    },
+    /// `material` with its normal from a tangent-space normal map.
+    NormalMap {
+        material: Box<MaterialRef>,
+        map: TextureRef,
+    },
+    /// `material` with its normal bent by bumps `scale` times as high as `height`.
+    BumpMap {
+        material: Box<MaterialRef>,
+        height: TextureRef,
+        #[serde(default = "one")]
+        scale: f64,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+}
+
+/// Parameters of a `principled` material, each a texture, color or number.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledDesc {
+    pub base_color: Option<TextureRef>,
+    pub metallic: Option<TextureRef>,
+    pub roughness: Option<TextureRef>,
+    pub specular: Option<TextureRef>,
+    pub specular_tint: Option<TextureRef>,
+    pub sheen: Option<TextureRef>,
+    pub sheen_tint: Option<TextureRef>,
+    pub clearcoat: Option<TextureRef>,
+    pub clearcoat_gloss: Option<TextureRef>,
+    pub transmission: Option<TextureRef>,
+    pub ior: Option<TextureRef>,
+}
+
+/// A material given by name or inline.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(MaterialDesc),
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialRef,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "one")]
+        time1: f64,
+        radius: f64,
+        material: MaterialRef,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: MaterialRef,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: MaterialRef,
+    },
+    /// An indexed triangle mesh; `normals` and `uvs` are per vertex.
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        indices: Vec<[u32; 3]>,
+        normals: Option<Vec<[f64; 3]>>,
+        uvs: Option<Vec<(f64, f64)>>,
+        material: MaterialRef,
+    },
+    /// A Wavefront OBJ file; `material` is used for faces without an MTL material.
+    Obj {
+        path: String,
+        material: Option<MaterialRef>,
+    },
+    /// A PLY file; without `material` it is diffuse, colored by its vertex colors if it has any.
+    Ply {
+        path: String,
+        material: Option<MaterialRef>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: TextureRef,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateX {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateZ {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    /// Rotation by `angle` degrees around `axis`.
+    Rotate {
+        angle: f64,
+        axis: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    Scale {
+        factors: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    /// An affine 4x4 matrix, given as rows; the last row must be `[0, 0, 0, 1]`.
+    Transform {
+        matrix: [[f64; 4]; 4],
+        object: Box<ObjectDesc>,
+    },
+    /// A placement of an entry of `prototypes`: scaled, rotated around x, y and z in that
+    /// order, then translated. `material` replaces the materials of the prototype.
+    Instance {
+        prototype: String,
+        #[serde(default = "ones")]
+        scale: [f64; 3],
+        #[serde(default)]
+        rotate_x: f64,
+        #[serde(default)]
+        rotate_y: f64,
+        #[serde(default)]
+        rotate_z: f64,
+        #[serde(default)]
+        translate: [f64; 3],
+        material: Option<MaterialRef>,
+    },
+    /// A group of objects, optionally with its own BVH.
+    List {
+        objects: Vec<ObjectDesc>,
+        #[serde(default)]
+        bvh: bool,
+    },
+}
+
+/// Separates the key of an error inside a `type`-tagged description from its message.
+const KEY_END: char = '\u{1f}';
+
+/// Deserializes a `type`-tagged description in two steps: into a JSON object, then, as
+/// `{ "<type>": { <other fields> } }`, into the variant with `variant`, the derived
+/// deserializer of the externally tagged form. Serde buffers internally tagged enums out
+/// of sight of `serde_path_to_error`, so the second step tracks the path itself and puts
+/// the key of an error before `KEY_END` in the message, for `split_key`.
+fn deserialize_tagged<'de, D, T>(
+    deserializer: D,
+    variant: impl FnOnce(
+        serde_path_to_error::Deserializer<serde_json::Value>,
+    ) -> Result<T, serde_json::Error>,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut fields = serde_json::Map::deserialize(deserializer)?;
+    let tag = match fields.remove("type") {
+        Some(serde_json::Value::String(tag)) => tag,
+        Some(tag) => {
+            return Err(de::Error::custom(format!(
+                "`type` must be a string, got {}",
+                tag
+            )))
+        }
+        None => return Err(de::Error::missing_field("type")),
+    };
+    let value = serde_json::Value::Object(
+        [(tag, serde_json::Value::Object(fields))]
+            .into_iter()
+            .collect(),
+    );
+    let mut track = serde_path_to_error::Track::new();
+    variant(serde_path_to_error::Deserializer::new(value, &mut track)).map_err(|e| {
+        let (inner, message) = split_key(&e.to_string());
+        // The first segment is the variant, which stands for the object itself; without
+        // one the variant was not found, which is the fault of `type`.
+        let path = track.path();
+        let key = if path.iter().len() == 0 {
+            "type".to_string()
+        } else {
+            path.iter().skip(1).fold(String::new(), |key, segment| {
+                join_key(&key, &segment.to_string())
+            })
+        };
+        de::Error::custom(format!("{}{}{}", join_key(&key, &inner), KEY_END, message))
+    })
+}
+
+/// Takes the key `deserialize_tagged` put into an error message out of it; the key is empty
+/// when there is none.
+fn split_key(message: &str) -> (String, String) {
+    match message.find(KEY_END) {
+        Some(end) => {
+            // Parsers may put their own text before the message, ending in a newline.
+            let start = message[..end].rfind('\n').map_or(0, |i| i + 1);
+            let rest = &message[end + KEY_END.len_utf8()..];
+            (
+                message[start..end].to_string(),
+                format!("{}{}", &message[..start], rest),
+            )
+        }
+        None => (String::new(), message.to_string()),
+    }
+}
+
+/// Appends the key `inner`, relative to `outer`, to it.
+fn join_key(outer: &str, inner: &str) -> String {
+    if inner.is_empty() || inner == "." {
+        outer.to_string()
+    } else if outer.is_empty() || outer == "." {
+        inner.to_string()
+    } else if inner.starts_with('[') {
+        format!("{}{}", outer, inner)
+    } else {
+        format!("{}.{}", outer, inner)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextureDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_tagged(deserializer, |variant| TextureDesc::deserialize(variant))
+    }
+}
+
+impl<'de> Deserialize<'de> for MaterialDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_tagged(deserializer, |variant| MaterialDesc::deserialize(variant))
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_tagged(deserializer, |variant| ObjectDesc::deserialize(variant))
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn ones() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn to_array(v: Vec3) -> [f64; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn vec3(a: [f64; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+/// Reads a `.json` or `.toml` scene file and builds it.
+pub fn load_scene(path: &Path, bvh_options: BvhOptions) -> Result<Scene, SceneFileError> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| SceneFileError::Io(path.to_path_buf(), e))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let desc = match extension.as_deref() {
+        Some("json") => parse_json(&text)?,
+        Some("toml") => parse_toml(&text)?,
+        _ => return Err(SceneFileError::UnknownFormat(path.to_path_buf())),
+    };
+    desc.build(path.parent().unwrap_or_else(|| Path::new("")), bvh_options)
+}
+
+pub fn parse_json(text: &str) -> Result<SceneDesc, SceneFileError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(text);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let outer = e.path().to_string();
+        let (key, message) = split_key(&e.into_inner().to_string());
+        SceneFileError::Parse {
+            key: join_key(&outer, &key),
+            message,
+        }
+    })
+}
+
+pub fn parse_toml(text: &str) -> Result<SceneDesc, SceneFileError> {
+    let deserializer = toml::Deserializer::new(text);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let outer = e.path().to_string();
+        let (key, message) = split_key(&e.into_inner().to_string());
+        SceneFileError::Parse {
+            key: join_key(&outer, &key),
+            message: message.trim_end().to_string(),
+        }
+    })
+}
+
+/// Turns descriptions into objects, caching named textures and materials so they are shared.
+struct Builder<'a> {
+    desc: &'a SceneDesc,
+    textures: BTreeMap<String, TexturePtr>,
+    materials: BTreeMap<String, MaterialPtr>,
+    prototypes: BTreeMap<String, HittablePtr>,
+    /// Tables keys like `materials.red` of the named entries being built, to catch cycles.
+    resolving: BTreeSet<String>,
+    /// Directory that relative mesh and image paths are resolved against.
+    base_dir: &'a Path,
+    bvh: BvhBuilder,
+}
+
+impl SceneDesc {
+    /// Builds the scene, reading mesh and image files relative to `base_dir`, usually the
+    /// directory of the scene file.
+    pub fn build(&self, base_dir: &Path, bvh_options: BvhOptions) -> Result<Scene, SceneFileError> {
+        if self.width == Some(0) {
+            return Err(invalid("width", "width must be positive"));
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            if !(aspect_ratio.is_finite() && aspect_ratio > 0.0) {
+                return Err(invalid("aspect_ratio", "aspect ratio must be positive"));
+            }
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            if samples_per_pixel < 1 {
+                return Err(invalid(
+                    "samples_per_pixel",
+                    "samples per pixel must be positive",
+                ));
+            }
+        }
+
+        let mut builder = Builder {
+            desc: self,
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            prototypes: BTreeMap::new(),
+            resolving: BTreeSet::new(),
+            base_dir,
+            bvh: BvhBuilder::new(bvh_options),
+        };
+
+        let camera = CameraSetup {
+            lookfrom: vec3(self.camera.lookfrom),
+            lookat: vec3(self.camera.lookat),
+            vup: vec3(self.camera.vup),
+            vfov: self.camera.vfov,
+            aperture: self.camera.aperture,
+            focus_dist: self.camera.focus_dist,
+            time0: self.camera.time0,
+            time1: self.camera.time1,
+        };
+        let world = builder.list(&self.objects, self.bvh, "objects", &camera)?;
+        let mut lights = HittableList::new();
+        for (i, object) in self.objects.iter().enumerate() {
+            if builder.is_light(object) {
+                lights.add(builder.object(object, &format!("objects[{}]", i), &camera)?);
+            }
+        }
+
+        let mut scene = Scene {
+            world,
+            lights,
+            background: vec3(self.background),
+            camera,
+            bvh_stats: builder.bvh.finish(),
+            ..Scene::default()
+        };
+        if let Some(width) = self.width {
+            scene.width = width;
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            scene.aspect_ratio = aspect_ratio;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            scene.samples_per_pixel = samples_per_pixel;
+        }
+        Ok(scene)
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Marks the named `entry` as being built, failing at `key` if it already is: the
+    /// entry refers to itself, directly or through others.
+    fn enter(&mut self, entry: &str, name: &str, key: &str) -> Result<(), SceneFileError> {
+        if !self.resolving.insert(entry.to_string()) {
+            return Err(invalid(key, format!("`{}` refers to itself", name)));
+        }
+        Ok(())
+    }
+
+    fn texture(&mut self, desc: &TextureDesc, key: &str) -> Result<TexturePtr, SceneFileError> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Some(Arc::new(SolidColor::new(vec3(*color)))),
+            TextureDesc::Checker { even, odd } => {
+                let even = self.texture_ref(even, &format!("{}.even", key))?;
+                let odd = self.texture_ref(odd, &format!("{}.odd", key))?;
+                Some(Arc::new(CheckerTexture::new(even, odd)))
+            }
+            TextureDesc::Noise { scale } => Some(Arc::new(NoiseTexture::new(*scale))),
+            TextureDesc::VertexColor {} => Some(Arc::new(VertexColorTexture)),
+            TextureDesc::Image { path } => {
+                match ImageTecture::load(&self.base_dir.join(path).to_string_lossy()) {
+                    Ok(image) => Some(Arc::new(image)),
+                    Err(e) => return Err(invalid(&format!("{}.path", key), e.to_string())),
+                }
+            }
+        })
+    }
+
+    fn texture_ref(&mut self, r: &TextureRef, key: &str) -> Result<TexturePtr, SceneFileError> {
+        match r {
+            TextureRef::Color(color) => Ok(Some(Arc::new(SolidColor::new(vec3(*color))))),
+            TextureRef::Gray(value) => Ok(Some(Arc::new(SolidColor::scalar(*value)))),
+            TextureRef::Inline(desc) => self.texture(desc, key),
+            TextureRef::Named(name) => {
+                if let Some(texture) = self.textures.get(name) {
+                    return Ok(texture.clone());
+                }
+                let desc = self.desc.textures.get(name).ok_or_else(|| {
+                    invalid(key, format!("no texture named `{}` in `textures`", name))
+                })?;
+                let entry = format!("textures.{}", name);
+                self.enter(&entry, name, key)?;
+                let texture = self.texture(desc, &entry);
+                self.resolving.remove(&entry);
+                let texture = texture?;
+                self.textures.insert(name.clone(), texture.clone());
+                Ok(texture)
+            }
+        }
+    }
+
+    fn material(&mut self, desc: &MaterialDesc, key: &str) -> Result<MaterialPtr, SceneFileError> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                let albedo = self.texture_ref(albedo, &format!("{}.albedo", key))?;
+                Some(Arc::new(Lambertian::new1(albedo)))
+            }
+            MaterialDesc::Metal {
+                albedo,
+                preset,
+                eta,
+                k,
+                roughness,
+            } => Some(Arc::new(match (albedo, preset, eta, k) {
+                (Some(albedo), None, None, None) => {
+                    Conductor::from_reflectance(&vec3(*albedo), *roughness)
+                }
+                (None, Some(preset), None, None) => Conductor::from_preset(*preset, *roughness),
+                (None, None, Some(eta), Some(k)) => {
+                    Conductor::new(vec3(*eta), vec3(*k), *roughness)
+                }
+                _ => {
+                    return Err(invalid(
+                        key,
+                        "a metal needs exactly one of `albedo`, `preset`, or `eta` and `k`",
+                    ))
+                }
+            })),
+            MaterialDesc::Dielectric {
+                ir,
+                roughness,
+                absorption,
+            } => Some(Arc::new(Dielectric::new1(
+                *ir,
+                *roughness,
+                vec3(*absorption),
+            ))),
+            MaterialDesc::Principled(desc) => {
+                let mut material =
+                    Principled::new(Some(Arc::new(SolidColor::new(Color1::new(0.8, 0.8, 0.8)))));
+                for (texture, r, name) in [
+                    (&mut material.base_color, &desc.base_color, "base_color"),
+                    (&mut material.metallic, &desc.metallic, "metallic"),
+                    (&mut material.roughness, &desc.roughness, "roughness"),
+                    (&mut material.specular, &desc.specular, "specular"),
+                    (
+                        &mut material.specular_tint,
+                        &desc.specular_tint,
+                        "specular_tint",
+                    ),
+                    (&mut material.sheen, &desc.sheen, "sheen"),
+                    (&mut material.sheen_tint, &desc.sheen_tint, "sheen_tint"),
+                    (&mut material.clearcoat, &desc.clearcoat, "clearcoat"),
+                    (
+                        &mut material.clearcoat_gloss,
+                        &desc.clearcoat_gloss,
+                        "clearcoat_gloss",
+                    ),
+                    (
+                        &mut material.transmission,
+                        &desc.transmission,
+                        "transmission",
+                    ),
+                    (&mut material.ior, &desc.ior, "ior"),
+                ] {
+                    if let Some(r) = r {
+                        *texture = self.texture_ref(r, &format!("{}.{}", key, name))?;
+                    }
+                }
+                Some(Arc::new(material))
+            }
+            MaterialDesc::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let first = self.material_ref(first, &format!("{}.first", key))?;
+                let second = self.material_ref(second, &format!("{}.second", key))?;
+                let mask = self.texture_ref(mask, &format!("{}.mask", key))?;
+                Some(Arc::new(MixMaterial::new(first, second, mask)))
+            }
+            MaterialDesc::NormalMap { material, map } => {
+                let material = self.material_ref(material, &format!("{}.material", key))?;
+                let map = self.texture_ref(map, &format!("{}.map", key))?;
+                Some(Arc::new(NormalMap::new(material, map)))
+            }
+            MaterialDesc::BumpMap {
+                material,
+                height,
+                scale,
+            } => {
+                let material = self.material_ref(material, &format!("{}.material", key))?;
+                let height = self.texture_ref(height, &format!("{}.height", key))?;
+                Some(Arc::new(BumpMap::new(material, height, *scale)))
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                let emit = self.texture_ref(emit, &format!("{}.emit", key))?;
+                Some(Arc::new(DiffuseLight::new(emit)))
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                let albedo = self.texture_ref(albedo, &format!("{}.albedo", key))?;
+                Some(Arc::new(Isotropic::new1(albedo)))
+            }
+        })
+    }
+
+    fn material_ref(&mut self, r: &MaterialRef, key: &str) -> Result<MaterialPtr, SceneFileError> {
+        match r {
+            MaterialRef::Inline(desc) => self.material(desc, key),
+            MaterialRef::Named(name) => {
+                if let Some(material) = self.materials.get(name) {
+                    return Ok(material.clone());
+                }
+                let desc = self.desc.materials.get(name).ok_or_else(|| {
+                    invalid(key, format!("no material named `{}` in `materials`", name))
+                })?;
+                let entry = format!("materials.{}", name);
+                self.enter(&entry, name, key)?;
+                let material = self.material(desc, &entry);
+                self.resolving.remove(&entry);
+                let material = material?;
+                self.materials.insert(name.clone(), material.clone());
+                Ok(material)
+            }
+        }
+    }
+
+    /// Whether `desc` is an emitter that can be sampled towards.
+    fn is_light(&self, desc: &ObjectDesc) -> bool {
+        let material = match desc {
+            ObjectDesc::Sphere { material, .. }
+            | ObjectDesc::XyRect { material, .. }
+            | ObjectDesc::XzRect { material, .. }
+            | ObjectDesc::YzRect { material, .. }
+            | ObjectDesc::Box { material, .. } => material,
+            ObjectDesc::Translate { object, .. }
+            | ObjectDesc::RotateX { object, .. }
+            | ObjectDesc::RotateY { object, .. }
+            | ObjectDesc::RotateZ { object, .. }
+            | ObjectDesc::Rotate { object, .. }
+            | ObjectDesc::Scale { object, .. }
+            | ObjectDesc::Transform { object, .. } => return self.is_light(object),
+            _ => return false,
+        };
+        let desc = match material {
+            MaterialRef::Inline(desc) => Some(desc),
+            MaterialRef::Named(name) => self.desc.materials.get(name),
+        };
+        matches!(desc, Some(MaterialDesc::DiffuseLight { .. }))
+    }
+
+    /// Builds the prototype called `name` on first use; later uses share it.
+    fn prototype(
+        &mut self,
+        name: &str,
+        key: &str,
+        camera: &CameraSetup,
+    ) -> Result<HittablePtr, SceneFileError> {
+        if let Some(prototype) = self.prototypes.get(name) {
+            return Ok(prototype.clone());
+        }
+        let objects = self.desc.prototypes.get(name).ok_or_else(|| {
+            invalid(
+                key,
+                format!("no prototype named `{}` in `prototypes`", name),
+            )
+        })?;
+        let entry = format!("prototypes.{}", name);
+        if objects.is_empty() {
+            return Err(invalid(&entry, "a prototype needs at least one object"));
+        }
+        self.enter(&entry, name, key)?;
+        let mut build = || {
+            let mut list = HittableList::new();
+            for (i, object) in objects.iter().enumerate() {
+                list.add(self.object(object, &format!("{}[{}]", entry, i), camera)?);
+            }
+            Ok(list)
+        };
+        let list = build();
+        self.resolving.remove(&entry);
+        let list = list?;
+        let prototype = Some(self.bvh.build(&list, camera.time0, camera.time1));
+        self.prototypes.insert(name.to_string(), prototype.clone());
+        Ok(prototype)
+    }
+
+    fn list(
+        &mut self,
+        objects: &[ObjectDesc],
+        bvh: bool,
+        key: &str,
+        camera: &CameraSetup,
+    ) -> Result<HittableList, SceneFileError> {
+        let mut list = HittableList::new();
+        for (i, object) in objects.iter().enumerate() {
+            list.add(self.object(object, &format!("{}[{}]", key, i), camera)?);
+        }
+        if bvh && !list.objects.is_empty() {
+            let node = self.bvh.build(&list, camera.time0, camera.time1);
+            list = HittableList::new();
+            list.add(Some(node));
+        }
+        Ok(list)
+    }
+
+    fn object(
+        &mut self,
+        desc: &ObjectDesc,
+        key: &str,
+        camera: &CameraSetup,
+    ) -> Result<HittablePtr, SceneFileError> {
+        let material_key = format!("{}.material", key);
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(Sphere::new(vec3(*center), *radius, material)))
+            }
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                if time1 <= time0 {
+                    return Err(invalid(
+                        &format!("{}.time1", key),
+                        "time1 must be greater than time0",
+                    ));
+                }
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(MovingSphere::new(
+                    vec3(*center0),
+                    vec3(*center1),
+                    *time0,
+                    *time1,
+                    *radius,
+                    material,
+                )))
+            }
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(XyRect::new(*x0, *x1, *y0, *y1, *k, material)))
+            }
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(XzRect::new(*x0, *x1, *z0, *z1, *k, material)))
+            }
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(YzRect::new(*y0, *y1, *z0, *z1, *k, material)))
+            }
+            ObjectDesc::Box { min, max, material } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(Box1::new(vec3(*min), vec3(*max), material)))
+            }
+            ObjectDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+                material,
+            } => {
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(Triangle::new(
+                    vertices.map(vec3),
+                    normals.map(|n| n.map(vec3)),
+                    *uvs,
+                    material,
+                )))
+            }
+            ObjectDesc::Mesh {
+                positions,
+                indices,
+                normals,
+                uvs,
+                material,
+            } => {
+                let data = MeshData {
+                    positions: positions.iter().copied().map(vec3).collect(),
+                    normals: normals
+                        .as_ref()
+                        .map(|n| n.iter().copied().map(vec3).collect()),
+                    uvs: uvs.clone(),
+                    colors: None,
+                    indices: indices.clone(),
+                };
+                data.validate().map_err(|e| invalid(key, e))?;
+                let material = self.material_ref(material, &material_key)?;
+                Some(Arc::new(TriangleMesh::new(data, material)))
+            }
+            ObjectDesc::Obj { path, material } => {
+                let material: MaterialPtr = match material {
+                    Some(material) => self.material_ref(material, &material_key)?,
+                    None => Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73)))),
+                };
+                let list = load_obj(&self.base_dir.join(path), material)
+                    .map_err(|e| invalid(&format!("{}.path", key), e.to_string()))?;
+                Some(Arc::new(list))
+            }
+            ObjectDesc::Ply { path, material } => {
+                let data = load_ply(&self.base_dir.join(path))
+                    .map_err(|e| invalid(&format!("{}.path", key), e.to_string()))?;
+                let material: MaterialPtr = match material {
+                    Some(material) => self.material_ref(material, &material_key)?,
+                    None if data.colors.is_some() => Some(Arc::new(Lambertian::new1(Some(
+                        Arc::new(VertexColorTexture),
+                    )))),
+                    None => Some(Arc::new(Lambertian::new(&Color1::new(0.73, 0.73, 0.73)))),
+                };
+                Some(Arc::new(TriangleMesh::new(data, material)))
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => {
+                if *density <= 0.0 {
+                    return Err(invalid(
+                        &format!("{}.density", key),
+                        "density must be positive",
+                    ));
+                }
+                let boundary = self.object(boundary, &format!("{}.boundary", key), camera)?;
+                let albedo = self.texture_ref(albedo, &format!("{}.albedo", key))?;
+                Some(Arc::new(ConstantMedium::new(boundary, *density, albedo)))
+            }
+            ObjectDesc::Translate { offset, object } => {
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                Some(Arc::new(Translate::new(object, &vec3(*offset))))
+            }
+            ObjectDesc::RotateX { angle, object } => {
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                Some(Arc::new(RotateX::new(object, *angle)))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                Some(Arc::new(RotateY::new(object, *angle)))
+            }
+            ObjectDesc::RotateZ { angle, object } => {
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                Some(Arc::new(RotateZ::new(object, *angle)))
+            }
+            ObjectDesc::Rotate {
+                angle,
+                axis,
+                object,
+            } => {
+                if vec3(*axis).length_squared() == 0.0 {
+                    return Err(invalid(&format!("{}.axis", key), "axis must be non-zero"));
+                }
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                let transform = Transform::rotate(*angle, &vec3(*axis));
+                Some(Arc::new(Transformed::new(object, transform)))
+            }
+            ObjectDesc::Scale { factors, object } => {
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                let scaled = Scale::new(object, &vec3(*factors)).ok_or_else(|| {
+                    invalid(
+                        &format!("{}.factors", key),
+                        "scale factors must be non-zero",
+                    )
+                })?;
+                Some(Arc::new(scaled))
+            }
+            ObjectDesc::Transform { matrix, object } => {
+                let key_matrix = format!("{}.matrix", key);
+                if matrix[3] != [0.0, 0.0, 0.0, 1.0] {
+                    return Err(invalid(&key_matrix, "the last row must be [0, 0, 0, 1]"));
+                }
+                let transform = Transform::new(*matrix)
+                    .ok_or_else(|| invalid(&key_matrix, "the matrix is not invertible"))?;
+                let object = self.object(object, &format!("{}.object", key), camera)?;
+                Some(Arc::new(Transformed::new(object, transform)))
+            }
+            ObjectDesc::Instance {
+                prototype,
+                scale,
+                rotate_x,
+                rotate_y,
+                rotate_z,
+                translate,
+                material,
+            } => {
+                let prototype = self.prototype(prototype, &format!("{}.prototype", key), camera)?;
+                let scale = Transform::scale(&vec3(*scale)).ok_or_else(|| {
+                    invalid(&format!("{}.scale", key), "scale factors must be non-zero")
+                })?;
+                let transform = Transform::translate(&vec3(*translate))
+                    * Transform::rotate_z(*rotate_z)
+                    * Transform::rotate_y(*rotate_y)
+                    * Transform::rotate_x(*rotate_x)
+                    * scale;
+                let material = match material {
+                    Some(material) => self.material_ref(material, &format!("{}.material", key))?,
+                    None => None,
+                };
+                Some(Arc::new(Instance::new(prototype, transform, material)))
+            }
+            ObjectDesc::List { objects, bvh } => {
+                let list = self.list(objects, *bvh, &format!("{}.objects", key), camera)?;
+                Some(Arc::new(list))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::{BvhLayout, SplitMethod};
+    use crate::vec3::Point3;
+
+    #[test]
+    fn test_toml_scene() {
+        let desc = parse_toml(
+            r#"
+            background = [0.7, 0.8, 1.0]
+            width = 200
+
+            [camera]
+            lookfrom = [0.0, 0.0, 5.0]
+
+            [materials.red]
+            type = "lambertian"
+            albedo = [0.65, 0.05, 0.05]
+
+            [[objects]]
+            type = "sphere"
+            center = [0.0, 0.0, 0.0]
+            radius = 1.0
+            material = "red"
+
+            [[objects]]
+            type = "rotate_y"
+            angle = 15.0
+            object = { type = "box", min = [0.0, 0.0, 0.0], max = [1.0, 1.0, 1.0], material = { type = "metal", albedo = [0.8, 0.8, 0.8] } }
+
+            [[objects]]
+            type = "sphere"
+            center = [3.0, 0.0, 0.0]
+            radius = 1.0
+            material = { type = "metal", preset = "aluminum", fuzz = 0.3 }
+
+            [[objects]]
+            type = "sphere"
+            center = [-3.0, 0.0, 0.0]
+            radius = 1.0
+            material = { type = "principled", base_color = [0.9, 0.6, 0.2], metallic = 1, roughness = { type = "noise", scale = 4.0 }, clearcoat = 0.5 }
+
+            [[objects]]
+            type = "sphere"
+            center = [0.0, -3.0, 0.0]
+            radius = 1.0
+            material = { type = "mix", first = { type = "metal", preset = "silver" }, second = "red", mask = { type = "noise", scale = 2.0 } }
+
+            [[objects]]
+            type = "sphere"
+            center = [0.0, 3.0, 0.0]
+            radius = 1.0
+            material = { type = "bump_map", material = "red", height = { type = "noise", scale = 4.0 }, scale = 0.05 }
+
+            [[objects]]
+            type = "transform"
+            matrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 3.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]
+            object = { type = "scale", factors = [2.0, 1.0, 0.5], object = { type = "xz_rect", x0 = -1.0, x1 = 1.0, z0 = -1.0, z1 = 1.0, k = 0.0, material = { type = "diffuse_light", emit = [4.0, 4.0, 4.0] } } }
+            "#,
+        )
+        .unwrap();
+        let scene = desc.build(Path::new(""), BvhOptions::default()).unwrap();
+        assert_eq!(scene.world.objects.len(), 7);
+        assert_eq!(scene.lights.objects.len(), 1);
+        assert_eq!(scene.width, 200);
+        assert_eq!(scene.camera.lookfrom, Point3::new(0.0, 0.0, 5.0));
+        assert_eq!(scene.background, Color1::new(0.7, 0.8, 1.0));
+    }
+
+    #[test]
+    fn test_error_points_at_key() {
+        let err = parse_json(
+            r#"{ "objects": [
+                { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "white" },
+                { "type": "sphere", "center": [0, 0, 0], "radius": "big", "material": "white" }
+            ] }"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "at `objects[1].radius`: invalid type: string \"big\", expected f64 at line 4 column 13"
+        );
+
+        let err = parse_json(
+            r#"{ "objects": [ { "type": "translate", "offset": [0, 0, 0], "object":
+                { "type": "list", "objects": [ { "type": "sphere", "center": [0, 0, "x"], "radius": 1, "material": "white" } ] } } ] }"#,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().starts_with(
+            "at `objects[0].object.objects[0].center[2]`: invalid type: string \"x\""
+        ));
+
+        let err = parse_json(r#"{ "objects": [ { "type": "sphre", "radius": 1 } ] }"#)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("at `objects[0].type`: unknown variant `sphre`"));
+
+        let err = parse_toml(
+            "[materials.shiny]\ntype = \"metal\"\nroughness = \"x\"\n[[objects]]\ntype = \"list\"\nobjects = []\n",
+        )
+        .err()
+        .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("at `materials.shiny.roughness`: "));
+        assert!(err
+            .to_string()
+            .ends_with("invalid type: string \"x\", expected f64"));
+
+        let desc = parse_json(
+            r#"{ "objects": [
+                { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "white" }
+            ] }"#,
+        )
+        .unwrap();
+        let err = desc
+            .build(Path::new(""), BvhOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "at `objects[0].material`: no material named `white` in `materials`"
+        );
+
+        let desc = parse_json(
+            r#"{ "materials": { "shiny": { "type": "metal", "albedo": [1, 1, 1], "preset": "gold" } },
+                 "objects": [
+                { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "shiny" }
+            ] }"#,
+        )
+        .unwrap();
+        let err = desc
+            .build(Path::new(""), BvhOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "at `materials.shiny`: a metal needs exactly one of `albedo`, `preset`, or `eta` and `k`"
+        );
+
+        for (settings, expected) in [
+            (r#""width": 0"#, "at `width`: width must be positive"),
+            (
+                r#""aspect_ratio": 0"#,
+                "at `aspect_ratio`: aspect ratio must be positive",
+            ),
+            (
+                r#""aspect_ratio": -1.5"#,
+                "at `aspect_ratio`: aspect ratio must be positive",
+            ),
+            (
+                r#""samples_per_pixel": 0"#,
+                "at `samples_per_pixel`: samples per pixel must be positive",
+            ),
+        ] {
+            let err = parse_json(&format!(r#"{{ {}, "objects": [] }}"#, settings))
+                .unwrap()
+                .build(Path::new(""), BvhOptions::default())
+                .err()
+                .unwrap();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_paths_are_relative_to_scene_file() {
+        let dir = std::env::temp_dir().join(format!("raytracer-scene-{}", std::process::id()));
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(assets.join("textures")).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(assets.join("textures").join("red.png"))
+            .unwrap();
+        std::fs::write(
+            assets.join("quad.mtl"),
+            "newmtl red\nKd 0.5 0.5 0.5\nmap_Kd textures/red.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            assets.join("quad.obj"),
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nusemtl red\nf 1 2 3 4\n",
+        )
+        .unwrap();
+        std::fs::write(
+            assets.join("tri.ply"),
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+             end_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n",
+        )
+        .unwrap();
+        let scene_path = dir.join("scene.json");
+        std::fs::write(
+            &scene_path,
+            r#"{ "objects": [
+                { "type": "obj", "path": "assets/quad.obj" },
+                { "type": "ply", "path": "assets/tri.ply" },
+                { "type": "sphere", "center": [0, 0, 0], "radius": 1,
+                  "material": { "type": "lambertian", "albedo": { "type": "image", "path": "assets/textures/red.png" } } }
+            ] }"#,
+        )
+        .unwrap();
+        let scene = load_scene(&scene_path, BvhOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(scene.unwrap().world.objects.len(), 3);
+    }
+
+    #[test]
+    fn test_cycles_are_errors() {
+        let sphere_a = r#""objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "a" } ]"#;
+        for (desc, expected) in [
+            (
+                format!(
+                    r#"{{ "materials": {{ "a": {{ "type": "mix", "first": "a", "second": "a", "mask": 0.5 }} }}, {} }}"#,
+                    sphere_a
+                ),
+                "at `materials.a.first`: `a` refers to itself",
+            ),
+            (
+                r#"{ "textures": {
+                         "a": { "type": "checker", "even": "b", "odd": [1, 1, 1] },
+                         "b": { "type": "checker", "even": [0, 0, 0], "odd": "a" } },
+                     "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 1,
+                                    "material": { "type": "lambertian", "albedo": "a" } } ] }"#
+                    .to_string(),
+                "at `textures.b.odd`: `a` refers to itself",
+            ),
+            (
+                format!(
+                    r#"{{ "materials": {{ "a": {{ "type": "normal_map", "material": "a", "map": [0.5, 0.5, 1] }} }}, {} }}"#,
+                    sphere_a
+                ),
+                "at `materials.a.material`: `a` refers to itself",
+            ),
+            (
+                format!(
+                    r#"{{ "materials": {{ "a": {{ "type": "bump_map", "material": "a", "height": {{ "type": "noise", "scale": 4 }} }} }}, {} }}"#,
+                    sphere_a
+                ),
+                "at `materials.a.material`: `a` refers to itself",
+            ),
+            (
+                r#"{ "prototypes": {
+                         "p": [ { "type": "instance", "prototype": "q" } ],
+                         "q": [ { "type": "instance", "prototype": "p" } ] },
+                     "objects": [ { "type": "instance", "prototype": "p" } ] }"#
+                    .to_string(),
+                "at `prototypes.q[0].prototype`: `p` refers to itself",
+            ),
+        ] {
+            let err = parse_json(&desc)
+                .unwrap()
+                .build(Path::new(""), BvhOptions::default())
+                .err()
+                .unwrap();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_instances() {
+        let desc = parse_json(
+            r#"{
+                "prototypes": { "pair": [
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] } },
+                    { "type": "sphere", "center": [3, 0, 0], "radius": 1, "material": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] } }
+                ] },
+                "bvh": true,
+                "objects": [
+                    { "type": "instance", "prototype": "pair" },
+                    { "type": "instance", "prototype": "pair", "translate": [0, 0, 5], "rotate_y": 90 },
+                    { "type": "instance", "prototype": "pair", "scale": [2, 2, 2], "material": { "type": "metal", "albedo": [0.8, 0.8, 0.8] } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut builder = Builder {
+            desc: &desc,
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            prototypes: BTreeMap::new(),
+            resolving: BTreeSet::new(),
+            base_dir: Path::new(""),
+            bvh: BvhBuilder::new(BvhOptions::default()),
+        };
+        let camera = Scene::default().camera;
+        let world = builder
+            .list(&desc.objects, true, "objects", &camera)
+            .unwrap();
+        assert_eq!(world.objects.len(), 1);
+        assert_eq!(builder.prototypes.len(), 1);
+        // The builder and the three instances hold the one prototype.
+        let prototype = builder.prototypes["pair"].as_ref().unwrap();
+        assert_eq!(Arc::strong_count(prototype), 4);
+
+        // The prototype and the top level each get a BVH, built with the options given.
+        let options = BvhOptions {
+            method: SplitMethod::Median,
+            layout: BvhLayout::Tree,
+            threads: 2,
+        };
+        let scene = desc.build(Path::new(""), options).unwrap();
+        assert_eq!(scene.bvh_stats.len(), 2);
+        assert_eq!(scene.bvh_stats[0].primitives, 2);
+        assert_eq!(scene.bvh_stats[1].primitives, 3);
+        assert!(scene
+            .bvh_stats
+            .iter()
+            .all(|stats| stats.method == SplitMethod::Median));
+
+        let desc = parse_json(r#"{ "objects": [ { "type": "instance", "prototype": "tree" } ] }"#)
+            .unwrap();
+        let err = desc
+            .build(Path::new(""), BvhOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "at `objects[0].prototype`: no prototype named `tree` in `prototypes`"
+        );
+    }
+}
@@ -1,5 +1,7 @@
-use crate::vec3::Point3;
-use crate::{Hittable, Vec3};
+use crate::onb::Onb;
+use crate::sampler::next_2d;
+use crate::vec3::{Color1, Point3};
+use crate::{Hittable, Vec3, INFINITY};
 
 use crate::aabb::Aabb;
 use crate::hittable::HitRecord;
@@ -31,6 +33,21 @@ pub fn get_sphere_uv(p: &Point3, u: &mut f64, v: &mut f64) {
     *v = theta / PI;
 }
 
+/// `(dpdu, dpdv)` at the point with outward normal `n` on a sphere of `radius`, for the
+/// parametrization of `get_sphere_uv`. `dpdv` has no direction at the poles, where it is
+/// taken along the meridian `u` = 0.
+pub fn get_sphere_tangents(n: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    let dpdu = Vec3::new(n.z, 0.0, -n.x) * (2.0 * PI * radius);
+    let sin_theta = (n.x * n.x + n.z * n.z).sqrt();
+    let (cos_phi, sin_phi) = if sin_theta > 0.0 {
+        (-n.x / sin_theta, n.z / sin_theta)
+    } else {
+        (1.0, 0.0)
+    };
+    let dpdv = Vec3::new(n.y * cos_phi, sin_theta, -n.y * sin_phi) * (PI * radius);
+    (dpdu, dpdv)
+}
+
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let oc = r.origin() - self.center;
@@ -56,7 +73,9 @@ impl Hittable for Sphere {
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
         get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = get_sphere_tangents(&outward_normal, self.radius);
         rec.mat_ptr = self.mat_ptr.clone();
+        rec.vertex_color = Color1::new(1.0, 1.0, 1.0);
 
         true
     }
@@ -68,4 +87,66 @@ impl Hittable for Sphere {
         );
         true
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let cos_theta_max =
+            (1.0 - self.radius * self.radius / (self.center - *o).length_squared()).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, o: &Point3) -> Vec3 {
+        let direction = self.center - *o;
+        let uvw = Onb::build_from_w(&direction);
+        uvw.local(&random_to_sphere(self.radius, direction.length_squared()))
+    }
+}
+
+/// A direction around +z, uniform over the cone subtended by a sphere of `radius`
+/// at squared distance `distance_squared`.
+fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let (r1, r2) = next_2d();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+    Vec3::new(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_tangents() {
+        // The tangents are the derivatives of the point along `u` and `v`.
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0, None);
+        let point = |u: f64, v: f64| {
+            let (theta, phi) = (v * PI, u * 2.0 * PI);
+            sphere.center
+                + Vec3::new(
+                    -theta.sin() * phi.cos(),
+                    -theta.cos(),
+                    theta.sin() * phi.sin(),
+                ) * sphere.radius
+        };
+        let h = 1e-6;
+        for (u, v) in [(0.1, 0.3), (0.6, 0.5), (0.9, 0.8)] {
+            let r = Ray::new(
+                point(u, v) * 2.0 - sphere.center,
+                sphere.center - point(u, v),
+                0.0,
+            );
+            let mut rec = HitRecord::new();
+            assert!(sphere.hit(&r, 0.001, INFINITY, &mut rec));
+            assert!((rec.u - u).abs() < 1e-9 && (rec.v - v).abs() < 1e-9);
+            let dpdu = (point(u + h, v) - point(u - h, v)) / (2.0 * h);
+            let dpdv = (point(u, v + h) - point(u, v - h)) / (2.0 * h);
+            assert!((rec.dpdu - dpdu).length() < 1e-6, "{:?}", rec.dpdu);
+            assert!((rec.dpdv - dpdv).length() < 1e-6, "{:?}", rec.dpdv);
+        }
+    }
 }
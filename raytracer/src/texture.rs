@@ -1,5 +1,5 @@
 use crate::rtweekend::clamp;
-use crate::{perlin, vec3, Point3};
+use crate::{perlin, vec3, HitRecord, Point3};
 
 use perlin::Perlin;
 use std::sync::Arc;
@@ -9,6 +9,18 @@ static BYTES_PER_PIXEL: i32 = 3;
 
 pub trait Texture {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color1;
+
+    /// Looks the texture up at a hit; textures that need more than `u`, `v` and `p` override this.
+    fn value_at(&self, rec: &HitRecord) -> Color1 {
+        self.value(rec.u, rec.v, &rec.p)
+    }
+
+    /// Looks the texture up at a hit as a single number, the mean of its channels, for
+    /// textures that drive a scalar parameter.
+    fn scalar_at(&self, rec: &HitRecord) -> f64 {
+        let c = self.value_at(rec);
+        (c.x + c.y + c.z) / 3.0
+    }
 }
 
 pub struct SolidColor {
@@ -20,6 +32,11 @@ impl SolidColor {
         Self { color_value: c }
     }
 
+    /// A gray texture of `value` in every channel.
+    pub fn scalar(value: f64) -> Self {
+        Self::new(Color1::new(value, value, value))
+    }
+
     /*    pub fn new1(red: f64, green: f64, blue: f64) -> Self {
         Self {
             color_value: Color1::new(red, green, blue),
@@ -68,6 +85,28 @@ impl Texture for CheckerTexture {
     }
 }
 
+/// A texture multiplied channel by channel by a color, like an MTL `map_Kd` by its `Kd`.
+pub struct TintTexture {
+    texture: Arc<dyn Texture + Send + Sync>,
+    tint: Color1,
+}
+
+impl TintTexture {
+    pub fn new(texture: Arc<dyn Texture + Send + Sync>, tint: Color1) -> Self {
+        Self { texture, tint }
+    }
+}
+
+impl Texture for TintTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color1 {
+        Color1::elemul(self.texture.value(u, v, p), self.tint)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color1 {
+        Color1::elemul(self.texture.value_at(rec), self.tint)
+    }
+}
+
 pub struct NoiseTexture {
     noise: Perlin,
     scale: f64,
@@ -99,15 +138,18 @@ pub struct ImageTecture {
 
 impl ImageTecture {
     pub fn new(filename: &str) -> Self {
-        let _components_per_pixel = BYTES_PER_PIXEL;
-        let image = image::open(filename).expect("Failed to load image");
+        ImageTecture::load(filename).expect("Failed to load image")
+    }
+
+    pub fn load(filename: &str) -> Result<Self, image::ImageError> {
+        let image = image::open(filename)?;
         let data1 = image.to_rgb8().into_vec();
-        Self {
+        Ok(Self {
             data: data1,
             width: image.width() as i32,
             height: image.height() as i32,
             bytes_per_scanline: BYTES_PER_PIXEL * image.width() as i32,
-        }
+        })
     }
 
     pub fn get_pixel(&self, mut i: i32, mut j: i32) -> Color1 {
@@ -133,3 +175,16 @@ impl Texture for ImageTecture {
         self.get_pixel(i, j)
     }
 }
+
+/// The interpolated vertex colors of the mesh that was hit, e.g. from a PLY file.
+pub struct VertexColorTexture;
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color1 {
+        Color1::new(1.0, 1.0, 1.0)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color1 {
+        rec.vertex_color
+    }
+}
@@ -0,0 +1,178 @@
+//! Splitting the image into tiles that render threads take from a shared queue.
+
+use clap::ValueEnum;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A rectangle of pixels, `x0..x1` by `y0..y1` in rows from the top.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+}
+
+/// The order tiles are handed out in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TileOrder {
+    /// Left to right, top to bottom
+    Scanline,
+    /// Outwards from the center of the image
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other
+    Hilbert,
+}
+
+/// Splits a `width` by `height` image into tiles of at most `tile_size` pixels square;
+/// `tile_size` must be positive.
+pub fn make_tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let nx = width.div_ceil(tile_size);
+    let ny = height.div_ceil(tile_size);
+    let tile = |(tx, ty): (usize, usize)| Tile {
+        x0: tx * tile_size,
+        y0: ty * tile_size,
+        x1: ((tx + 1) * tile_size).min(width),
+        y1: ((ty + 1) * tile_size).min(height),
+    };
+    let positions: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..ny)
+            .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral(nx, ny),
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            let mut positions: Vec<(usize, usize)> = (0..ny)
+                .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+                .collect();
+            positions.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+            positions
+        }
+    };
+    positions.into_iter().map(tile).collect()
+}
+
+/// Walks a square spiral out from the center tile, keeping the positions inside the grid.
+fn spiral(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(nx * ny);
+    let (mut x, mut y) = (((nx as i64) - 1) / 2, ((ny as i64) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut direction = 0;
+    while positions.len() < nx * ny {
+        // Each step length is walked twice before it grows: right 1, down 1, left 2, up 2...
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction % 4];
+            for _ in 0..step {
+                if (0..nx as i64).contains(&x) && (0..ny as i64).contains(&y) {
+                    positions.push((x as usize, y as usize));
+                }
+                x += dx;
+                y += dy;
+            }
+            direction += 1;
+        }
+        step += 1;
+    }
+    positions
+}
+
+/// Distance of `(x, y)` along the Hilbert curve filling an `n` by `n` grid, `n` a power of two.
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve inside it starts and ends in the right corners.
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+/// Tiles shared by the render threads; each call to `next` hands out a tile no other thread
+/// gets, so fast threads simply take more tiles.
+pub struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+}
+
+impl TileQueue {
+    pub fn new(tiles: Vec<Tile>) -> Self {
+        Self {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn next(&self) -> Option<Tile> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_image() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = make_tiles(100, 70, 16, order);
+            assert_eq!(tiles.len(), 7 * 5);
+            let mut covered = vec![0; 100 * 70];
+            for tile in &tiles {
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        covered[y * 100 + x] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1), "{:?}", order);
+        }
+
+        // The spiral starts in the middle, and the Hilbert curve only steps to neighbors.
+        let spiral = make_tiles(100, 70, 16, TileOrder::Spiral);
+        assert_eq!((spiral[0].x0, spiral[0].y0), (48, 32));
+        let hilbert = make_tiles(128, 128, 16, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let dx = pair[0].x0.abs_diff(pair[1].x0);
+            let dy = pair[0].y0.abs_diff(pair[1].y0);
+            assert_eq!(dx + dy, 16);
+        }
+    }
+
+    #[test]
+    fn test_queue_hands_out_each_tile_once() {
+        let queue = TileQueue::new(make_tiles(64, 64, 8, TileOrder::Scanline));
+        let taken: Vec<Tile> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| std::iter::from_fn(|| queue.next()).collect::<Vec<_>>()))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        assert_eq!(taken.len(), 64);
+        let mut origins: Vec<_> = taken.iter().map(|t| (t.x0, t.y0)).collect();
+        origins.sort();
+        origins.dedup();
+        assert_eq!(origins.len(), 64);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::aabb::Aabb;
+use crate::{degrees_to_radians, Point3, Vec3, INFINITY};
+use std::ops::Mul;
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// An invertible affine transform, a 4x4 matrix acting on column vectors, kept together
+/// with its inverse. `a * b` applies `b` first, then `a`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    m: Matrix,
+    m_inv: Matrix,
+}
+
+impl Transform {
+    /// `None` when `m` is singular.
+    pub fn new(m: Matrix) -> Option<Self> {
+        Some(Self {
+            m,
+            m_inv: invert(&m)?,
+        })
+    }
+
+    pub fn translate(offset: &Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut m_inv = IDENTITY;
+        for a in 0..3 {
+            m[a][3] = offset[a];
+            m_inv[a][3] = -offset[a];
+        }
+        Self { m, m_inv }
+    }
+
+    /// `None` when a factor is zero.
+    pub fn scale(factors: &Vec3) -> Option<Self> {
+        let mut m = IDENTITY;
+        let mut m_inv = IDENTITY;
+        for a in 0..3 {
+            if factors[a] == 0.0 {
+                return None;
+            }
+            m[a][a] = factors[a];
+            m_inv[a][a] = 1.0 / factors[a];
+        }
+        Some(Self { m, m_inv })
+    }
+
+    /// Rotation by `angle` degrees counterclockwise around `axis`, looking down the axis.
+    pub fn rotate(angle: f64, axis: &Vec3) -> Self {
+        let a = axis.unit_vector();
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        let t = 1.0 - cos_theta;
+        let mut m = IDENTITY;
+        m[0][0] = a.x * a.x * t + cos_theta;
+        m[0][1] = a.x * a.y * t - a.z * sin_theta;
+        m[0][2] = a.x * a.z * t + a.y * sin_theta;
+        m[1][0] = a.y * a.x * t + a.z * sin_theta;
+        m[1][1] = a.y * a.y * t + cos_theta;
+        m[1][2] = a.y * a.z * t - a.x * sin_theta;
+        m[2][0] = a.z * a.x * t - a.y * sin_theta;
+        m[2][1] = a.z * a.y * t + a.x * sin_theta;
+        m[2][2] = a.z * a.z * t + cos_theta;
+        // Rotations are orthogonal, so the inverse is the transpose.
+        Self {
+            m,
+            m_inv: transpose(&m),
+        }
+    }
+
+    pub fn rotate_x(angle: f64) -> Self {
+        Self::rotate(angle, &Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    pub fn rotate_y(angle: f64) -> Self {
+        Self::rotate(angle, &Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    pub fn rotate_z(angle: f64) -> Self {
+        Self::rotate(angle, &Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Transforms a surface normal by the inverse transpose, which keeps it perpendicular
+    /// to the transformed surface. The result is not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m_inv;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// Determinant of the linear part, the factor by which volumes grow.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// The smallest box around the transformed corners of `bbox`.
+    pub fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+        let (lo, hi) = (bbox.min(), bbox.max());
+        for i in 0..8 {
+            let pick = |bit: usize, a: usize| if i & bit == 0 { lo[a] } else { hi[a] };
+            let corner = Point3::new(pick(1, 0), pick(2, 1), pick(4, 2));
+            let p = self.point(&corner);
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Aabb::new(min, max)
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            m: multiply(&self.m, &other.m),
+            m_inv: multiply(&other.m_inv, &self.m_inv),
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut r = [[0.0; 4]; 4];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    r
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut r = [[0.0; 4]; 4];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    r
+}
+
+/// Gauss-Jordan elimination with partial pivoting.
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let mut a = *m;
+    let mut inv = IDENTITY;
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let scale = 1.0 / a[col][col];
+        for j in 0..4 {
+            a[col][j] *= scale;
+            inv[col][j] *= scale;
+        }
+        for row in 0..4 {
+            if row != col {
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{Hittable, RotateY, Transformed};
+    use crate::rtweekend::seed_rng;
+    use crate::sphere::Sphere;
+    use crate::{HitRecord, Ray};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} vs {:?}", a, b);
+    }
+
+    #[test]
+    fn test_transform() {
+        let t = Transform::translate(&Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(30.0, &Vec3::new(1.0, 1.0, 0.0))
+            * Transform::scale(&Vec3::new(2.0, 0.5, -1.0)).unwrap();
+        let general = Transform::new(t.m).unwrap();
+        let p = Point3::new(0.3, -0.7, 2.0);
+        assert_close(t.inverse().point(&t.point(&p)), p);
+        assert_close(general.inverse().point(&general.point(&p)), p);
+
+        // A normal stays perpendicular to tangents after a non-uniform scale.
+        let (n, tangent) = (Vec3::new(1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        assert!((t.normal(&n) * t.vector(&tangent)).abs() < 1e-9);
+
+        assert_close(
+            Transform::rotate_z(90.0).vector(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert!((t.determinant() + 1.0).abs() < 1e-9);
+        assert!(Transform::new([[0.0; 4]; 4]).is_none());
+        assert!(Transform::scale(&Vec3::new(1.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn test_transformed_hittable() {
+        // RotateY is a thin wrapper and must agree with the general transform.
+        let sphere = || Some(Arc::new(Sphere::new(Point3::new(2.0, 0.0, 0.0), 1.0, None)) as _);
+        let rotated = RotateY::new(sphere(), 90.0);
+        let r = Ray::new(Point3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut rec = HitRecord::new();
+        assert!(rotated.hit(&r, 0.001, INFINITY, &mut rec));
+        assert_close(rec.p, Point3::new(0.0, 0.0, -1.0));
+        assert_close(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        // An ellipsoid with semi-axes 3, 1, 1 around the origin.
+        let ellipsoid = Transformed::new(
+            Some(Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, None))),
+            Transform::scale(&Vec3::new(3.0, 1.0, 1.0)).unwrap(),
+        );
+        let r = Ray::new(Point3::new(1.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(ellipsoid.hit(&r, 0.001, INFINITY, &mut rec));
+        let y = (1.0f64 - 1.0 / 9.0).sqrt();
+        assert_close(rec.p, Point3::new(1.0, y, 0.0));
+        assert_close(rec.normal, Vec3::new(1.0 / 9.0, y, 0.0).unit_vector());
+        let mut bbox = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0));
+        assert!(ellipsoid.bounding_box(0.0, 1.0, &mut bbox));
+        assert_close(bbox.max(), Point3::new(3.0, 1.0, 1.0));
+
+        // Sampling the ellipsoid as a light still gives a density over directions.
+        seed_rng(1);
+        let o = Point3::new(0.0, 4.0, 0.0);
+        let n = 200_000;
+        let total: f64 = (0..n)
+            .map(|_| ellipsoid.pdf_value(&o, &Vec3::random_unit_vector()))
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / n as f64;
+        assert!((total - 1.0).abs() < 0.03, "{}", total);
+        let v = ellipsoid.random(&o);
+        assert!(ellipsoid.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec));
+    }
+}
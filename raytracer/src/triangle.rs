@@ -0,0 +1,158 @@
+use crate::aabb::Aabb;
+use crate::onb::Onb;
+use crate::{Color1, HitRecord, Hittable, Material, Point3, Ray, Vec3};
+use std::sync::Arc;
+
+/// Padding applied to triangle bounding boxes so axis-aligned triangles never get a flat box.
+const PAD: f64 = 0.0001;
+
+/// Möller–Trumbore ray/triangle intersection.
+/// Returns `(t, b1, b2)` where `b1`, `b2` are the barycentric weights of `p1` and `p2`.
+pub fn intersect(
+    r: &Ray,
+    p0: &Point3,
+    p1: &Point3,
+    p2: &Point3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let e1 = *p1 - *p0;
+    let e2 = *p2 - *p0;
+    let pvec = Vec3::cross(&r.direction(), &e2);
+    let det = e1 * pvec;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - *p0;
+    let b1 = (tvec * pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = Vec3::cross(&tvec, &e1);
+    let b2 = (r.direction() * qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = (e2 * qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Fills `rec` for a hit at barycentric `(b1, b2)` of the triangle `p`.
+/// Without texture coordinates `u`/`v` are the barycentric weights themselves,
+/// and without vertex colors the vertex color is white.
+#[allow(clippy::too_many_arguments)]
+pub fn fill_record(
+    r: &Ray,
+    t: f64,
+    (b1, b2): (f64, f64),
+    p: [&Point3; 3],
+    normals: Option<[&Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    colors: Option<[&Color1; 3]>,
+    rec: &mut HitRecord,
+) {
+    let b0 = 1.0 - b1 - b2;
+    let geometric = Vec3::cross(&(*p[1] - *p[0]), &(*p[2] - *p[0])).unit_vector();
+
+    rec.t = t;
+    rec.p = r.at(t);
+    rec.set_face_normal(r, &geometric);
+    if let Some(n) = normals {
+        let shading = (*n[0] * b0 + *n[1] * b1 + *n[2] * b2).unit_vector();
+        // Keep the shading normal on the same side as the geometric one.
+        rec.normal = if shading * rec.normal < 0.0 {
+            -shading
+        } else {
+            shading
+        };
+    }
+    let uv = uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+    rec.u = uv[0].0 * b0 + uv[1].0 * b1 + uv[2].0 * b2;
+    rec.v = uv[0].1 * b0 + uv[1].1 * b1 + uv[2].1 * b2;
+    (rec.dpdu, rec.dpdv) = tangents(p, uv, &geometric);
+    rec.vertex_color = match colors {
+        Some(c) => *c[0] * b0 + *c[1] * b1 + *c[2] * b2,
+        None => Color1::new(1.0, 1.0, 1.0),
+    };
+}
+
+/// `(dpdu, dpdv)` of the triangle `p` with texture coordinates `uv`, solving
+/// `p[i] - p[2] = (u[i] - u[2]) dpdu + (v[i] - v[2]) dpdv`. Texture coordinates that do not
+/// span an area give an arbitrary frame around `normal`.
+fn tangents(p: [&Point3; 3], uv: [(f64, f64); 3], normal: &Vec3) -> (Vec3, Vec3) {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let (dp02, dp12) = (*p[0] - *p[2], *p[1] - *p[2]);
+    let det = du02 * dv12 - dv02 * du12;
+    if det.abs() < 1e-12 {
+        let uvw = Onb::build_from_w(normal);
+        return (uvw.u(), uvw.v());
+    }
+    (
+        (dp02 * dv12 - dp12 * dv02) / det,
+        (dp12 * du02 - dp02 * du12) / det,
+    )
+}
+
+pub fn bounding_box(p0: &Point3, p1: &Point3, p2: &Point3) -> Aabb {
+    let small = Point3::new(
+        p0.x.min(p1.x).min(p2.x) - PAD,
+        p0.y.min(p1.y).min(p2.y) - PAD,
+        p0.z.min(p1.z).min(p2.z) - PAD,
+    );
+    let big = Point3::new(
+        p0.x.max(p1.x).max(p2.x) + PAD,
+        p0.y.max(p1.y).max(p2.y) + PAD,
+        p0.z.max(p1.z).max(p2.z) + PAD,
+    );
+    Aabb::new(small, big)
+}
+
+/// A single triangle with optional per-vertex normals and texture coordinates.
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    mat_ptr: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl Triangle {
+    pub fn new(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        m: Option<Arc<dyn Material + Send + Sync>>,
+    ) -> Self {
+        Self {
+            vertices,
+            normals,
+            uvs,
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = &self.vertices;
+        let (t, b1, b2) = match intersect(r, p0, p1, p2, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let normals = self.normals.as_ref().map(|[n0, n1, n2]| [n0, n1, n2]);
+        fill_record(r, t, (b1, b2), [p0, p1, p2], normals, self.uvs, None, rec);
+        rec.mat_ptr = self.mat_ptr.clone();
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        let [p0, p1, p2] = &self.vertices;
+        *output_box = bounding_box(p0, p1, p2);
+        true
+    }
+}
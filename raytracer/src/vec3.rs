@@ -1,4 +1,6 @@
 use crate::rtweekend::{random_f64, random_f64_1};
+use crate::sampler::{next_1d, next_2d};
+use std::f64::consts::PI;
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -221,19 +223,30 @@ impl Vec3 {
         )
     }
 
+    /// Uniform in the unit ball: a uniform direction scaled by the cube root of a uniform
+    /// number, drawn from the sampler rather than by rejection.
     pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::random1(-1.0, 1.0);
-            if p.length_squared() >= 1.0 {
-                continue;
-            } else {
-                return p;
-            }
-        }
+        Vec3::random_unit_vector() * next_1d().cbrt()
     }
 
+    /// Uniform on the unit sphere, mapped from a 2D sample.
     pub fn random_unit_vector() -> Vec3 {
-        Vec3::random_in_unit_sphere().unit_vector()
+        let (r1, r2) = next_2d();
+        let z = 1.0 - 2.0 * r2;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * r1;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// A direction around +z with density cos(theta) / pi.
+    pub fn random_cosine_direction() -> Vec3 {
+        let (r1, r2) = next_2d();
+        let phi = 2.0 * PI * r1;
+        Vec3::new(
+            phi.cos() * r2.sqrt(),
+            phi.sin() * r2.sqrt(),
+            (1.0 - r2).sqrt(),
+        )
     }
 
     pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
@@ -289,15 +302,20 @@ impl Vec3 {
         }
     }
 
+    /// Uniform in the unit disk in the xy plane, using Shirley's concentric mapping so that
+    /// well spread samples stay well spread on the disk.
     pub fn random_in_unit_disk() -> Vec3 {
-        let mut p;
-        loop {
-            p = Vec3::new(random_f64_1(-1.0, 1.0), random_f64_1(-1.0, 1.0), 0.0);
-            if p.clone().length_squared() >= 1.0 {
-                continue;
-            };
-            return p;
+        let (r1, r2) = next_2d();
+        let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 }
 